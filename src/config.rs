@@ -0,0 +1,510 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use reqwest::Url;
+
+use retry::Backoff;
+use friend::Media;
+use page::cache::CacheSettings;
+
+/// The runtime configuration
+///
+/// The configuration is loaded from a JSON file, and every field can be
+/// overridden by an environment variable. See `Config::load`.
+#[derive(Debug, Clone)]
+pub struct Config
+{
+    /// The reddit bot client ID
+    pub id: String,
+    /// The reddit bot client secret
+    pub secret: String,
+    /// The reddit account username that the bot will use
+    pub user: String,
+    /// How the bot gets authorization for the reddit account
+    pub auth: Auth,
+    /// The reddit username of the maintainer
+    pub maintainer: String,
+    /// Where the bot will answer messages, and how
+    pub subreddits: Subreddits,
+    /// The wiki to look friends up on
+    pub wiki: Wiki,
+    /// The directory the bot keeps its persistent state in
+    pub state_dir: PathBuf,
+    /// How failed messages are retried
+    pub retry: Backoff,
+    /// How long looked up pages are cached
+    pub cache: CacheSettings,
+    /// The medias friends can be asked for in
+    pub medias: Vec<Media>,
+    /// The infobox facts to show in replies, in order
+    pub info_fields: Vec<InfoField>,
+}
+
+impl Config
+{
+    /// Load the configuration
+    ///
+    /// If `path` is given, the file is read as a JSON object first. Then any
+    /// of these environment variables that are set replace the matching field:
+    ///
+    /// - `LIBRARIAN_ID`
+    /// - `LIBRARIAN_SECRET`
+    /// - `LIBRARIAN_USER`
+    /// - `LIBRARIAN_PASS`
+    /// - `LIBRARIAN_AUTH`
+    /// - `LIBRARIAN_REDIRECT_URI`
+    /// - `LIBRARIAN_MAINTAINER`
+    pub fn load<P: AsRef<Path>>(path: Option<P>) -> Result<Config, ConfigError>
+    {
+        let mut raw = match path
+        {
+            Some(path) =>
+            {
+                let path = path.as_ref();
+                let file = File::open(path)
+                    .map_err(|e| ConfigError::Io(path.display().to_string(), e))?;
+                ::serde_json::from_reader::<_, RawConfig>(io::BufReader::new(file))
+                    .map_err(|e| ConfigError::Parse(path.display().to_string(), e))?
+            },
+            None => RawConfig::default(),
+        };
+
+        raw.override_env();
+        raw.validate()
+    }
+}
+
+/// Which messages the bot answers, and how it answers them
+///
+/// All subreddit names are compared in lowercase.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Subreddits
+{
+    /// Subreddits to answer comments in
+    ///
+    /// If this is empty, comments from any subreddit not in the blacklist are answered.
+    pub whitelist: Vec<String>,
+    /// Subreddits to never answer comments in
+    pub blacklist: Vec<String>,
+    /// Whether private messages are answered
+    pub allow_private: bool,
+    /// The reply policy for private messages, and any subreddit without an override
+    pub policy: Policy,
+    /// Reply policy overrides, by subreddit
+    pub overrides: HashMap<String, PolicyOverride>,
+}
+
+impl Subreddits
+{
+    /// Check if a message from a subreddit (or a private message, if `None`)
+    /// should be answered
+    pub fn allows(&self, subreddit: &Option<String>) -> bool
+    {
+        match subreddit
+        {
+            Some(sub) =>
+            {
+                let sub = sub.to_lowercase();
+
+                !self.blacklist.contains(&sub) &&
+                    (self.whitelist.is_empty() || self.whitelist.contains(&sub))
+            },
+            None => self.allow_private,
+        }
+    }
+
+    /// Get the reply policy for a subreddit (or a private message, if `None`)
+    pub fn policy(&self, subreddit: &Option<String>) -> Policy
+    {
+        let over = match subreddit
+        {
+            Some(sub) => self.overrides.get(&sub.to_lowercase()),
+            None => None,
+        };
+
+        match over
+        {
+            Some(over) => Policy
+            {
+                max_links: over.max_links.or(self.policy.max_links),
+                images: over.images.unwrap_or(self.policy.images),
+                excerpts: over.excerpts.unwrap_or(self.policy.excerpts),
+                info: over.info.unwrap_or(self.policy.info),
+            },
+            None => self.policy,
+        }
+    }
+
+    fn normalize(&mut self)
+    {
+        for sub in self.whitelist.iter_mut().chain(self.blacklist.iter_mut())
+        {
+            *sub = sub.to_lowercase();
+        }
+
+        self.overrides = self.overrides.drain()
+            .map(|(sub, over)| (sub.to_lowercase(), over))
+            .collect();
+    }
+}
+
+impl Default for Subreddits
+{
+    fn default() -> Subreddits
+    {
+        Subreddits
+        {
+            whitelist: vec![
+                "kemonofriends".to_owned(),
+                "japari_librarian".to_owned(),
+            ],
+            blacklist: Vec::new(),
+            allow_private: true,
+            policy: Policy::default(),
+            overrides: HashMap::new(),
+        }
+    }
+}
+
+/// How replies are formatted
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy
+{
+    /// The most page links to put in one reply, or `None` for no limit
+    pub max_links: Option<usize>,
+    /// Whether to link images alongside pages
+    pub images: bool,
+    /// Whether to quote the first paragraph of each page
+    pub excerpts: bool,
+    /// Whether to show a table of infobox facts for each page
+    pub info: bool,
+}
+
+impl Default for Policy
+{
+    fn default() -> Policy
+    {
+        Policy
+        {
+            max_links: None,
+            images: true,
+            excerpts: true,
+            info: true,
+        }
+    }
+}
+
+/// An infobox fact to show in replies
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InfoField
+{
+    /// The name of the fact, see `FriendInfo::get`
+    pub field: String,
+    /// The column heading to show it under
+    pub label: String,
+}
+
+impl InfoField
+{
+    fn new(field: &str, label: &str) -> InfoField
+    {
+        InfoField
+        {
+            field: field.to_owned(),
+            label: label.to_owned(),
+        }
+    }
+}
+
+/// A partial reply policy
+///
+/// Any field left out is taken from the default policy.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PolicyOverride
+{
+    pub max_links: Option<usize>,
+    pub images: Option<bool>,
+    pub excerpts: Option<bool>,
+    pub info: Option<bool>,
+}
+
+/// A MediaWiki site
+#[derive(Debug, Clone)]
+pub struct Wiki
+{
+    /// The URL of the wiki's `api.php`
+    pub api: Url,
+    /// The wiki's article path, with `$1` in place of the page title
+    pub article_path: String,
+    /// A category that marks disambiguation pages, with the `Category:` prefix
+    ///
+    /// Pages are also found to be disambiguation pages through the
+    /// `disambiguation` page property, if the wiki has the Disambiguator extension.
+    pub disambiguation_category: Option<String>,
+    /// The widest an image can be before a thumbnail is linked instead
+    ///
+    /// `None` always links the original.
+    pub thumb_width: Option<u32>,
+    /// Whether to leave out files that aren't images, like videos or PDFs
+    pub only_images: bool,
+}
+
+/// How the bot gets authorization for the reddit account
+#[derive(Debug, Clone)]
+pub enum Auth
+{
+    /// A "script" app, which logs in with the account password
+    Password(String),
+    /// An "installed" or "web" app, which is authorized once with the
+    /// `authorize` command
+    ///
+    /// The refresh token from authorizing is kept in the state directory, so
+    /// the account password isn't needed.
+    RefreshToken
+    {
+        /// The app's redirect URI, which the `authorize` command listens on
+        redirect_uri: Url,
+    },
+}
+
+/// An error that might occur while loading the configuration
+#[derive(Debug)]
+pub enum ConfigError
+{
+    /// The config file could not be read
+    Io(String, io::Error),
+    /// The config file was not valid JSON, or a field had the wrong type
+    Parse(String, ::serde_json::Error),
+    /// A required field was not in the file or the environment
+    Missing(&'static str),
+    /// A field was present, but its value can't be used
+    Invalid(&'static str, String),
+}
+
+impl fmt::Display for ConfigError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self
+        {
+            ConfigError::Io(path, err) => write!(f,
+                "could not read config file \"{}\": {}", path, err),
+            ConfigError::Parse(path, err) => write!(f,
+                "malformed config file \"{}\": {}", path, err),
+            ConfigError::Missing(field) => write!(f,
+                "missing config field \"{}\" (set it in the config file or with {})",
+                field, env_name(field)),
+            ConfigError::Invalid(field, reason) => write!(f,
+                "invalid config field \"{}\": {}", field, reason),
+        }
+    }
+}
+
+fn env_name(field: &str) -> String
+{
+    format!("LIBRARIAN_{}", field.to_uppercase())
+}
+
+// ==============================
+// Serde structs below
+// ==============================
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RawConfig
+{
+    id: Option<String>,
+    secret: Option<String>,
+    user: Option<String>,
+    pass: Option<String>,
+    auth: Option<String>,
+    redirect_uri: Option<String>,
+    maintainer: Option<String>,
+    #[serde(default)]
+    subreddits: Subreddits,
+    #[serde(default)]
+    wiki: RawWiki,
+    state_dir: Option<PathBuf>,
+    #[serde(default)]
+    retry: Backoff,
+    #[serde(default)]
+    cache: CacheSettings,
+    info_fields: Option<Vec<InfoField>>,
+    medias: Option<Vec<Media>>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawWiki
+{
+    api: String,
+    article_path: String,
+    disambiguation_category: Option<String>,
+    thumb_width: Option<u32>,
+    only_images: bool,
+}
+
+impl Default for RawWiki
+{
+    fn default() -> RawWiki
+    {
+        RawWiki
+        {
+            api: "https://www.japari-library.com/w/api.php".to_owned(),
+            article_path: "https://www.japari-library.com/wiki/$1".to_owned(),
+            disambiguation_category: None,
+            thumb_width: Some(640),
+            only_images: true,
+        }
+    }
+}
+
+impl RawConfig
+{
+    fn override_env(&mut self)
+    {
+        fn var(field: &str, value: &mut Option<String>)
+        {
+            if let Ok(v) = env::var(env_name(field))
+            {
+                *value = Some(v);
+            }
+        }
+
+        var("id", &mut self.id);
+        var("secret", &mut self.secret);
+        var("user", &mut self.user);
+        var("pass", &mut self.pass);
+        var("auth", &mut self.auth);
+        var("redirect_uri", &mut self.redirect_uri);
+        var("maintainer", &mut self.maintainer);
+    }
+
+    fn validate(self) -> Result<Config, ConfigError>
+    {
+        fn required(field: &'static str, value: Option<String>) -> Result<String, ConfigError>
+        {
+            match value
+            {
+                Some(ref v) if v.trim().is_empty() => Err(ConfigError::Missing(field)),
+                Some(v) => Ok(v),
+                None => Err(ConfigError::Missing(field)),
+            }
+        }
+
+        let mut subreddits = self.subreddits;
+        subreddits.normalize();
+
+        let wiki = self.wiki;
+        let api = Url::parse(&wiki.api)
+            .map_err(|e| ConfigError::Invalid("wiki.api", format!(
+                "\"{}\" is not a valid URL: {}", wiki.api, e)))?;
+        if !wiki.article_path.contains("$1")
+        {
+            return Err(ConfigError::Invalid("wiki.article_path", format!(
+                "\"{}\" does not contain \"$1\"", wiki.article_path)));
+        }
+        if wiki.thumb_width == Some(0)
+        {
+            return Err(ConfigError::Invalid("wiki.thumb_width",
+                "thumbnails must be at least one pixel wide".to_owned()));
+        }
+
+        if self.retry.max_attempts == 0
+        {
+            return Err(ConfigError::Invalid("retry.max_attempts",
+                "there must be at least one attempt".to_owned()));
+        }
+
+        let medias = self.medias.unwrap_or_else(Media::defaults);
+        for media in medias.iter()
+        {
+            if media.name.trim().is_empty()
+            {
+                return Err(ConfigError::Invalid("medias",
+                    "every media needs a name".to_owned()));
+            }
+            if !media.suffix.starts_with('/')
+            {
+                return Err(ConfigError::Invalid("medias", format!(
+                    "the suffix for \"{}\" does not start with \"/\"", media.name)));
+            }
+        }
+
+        let user = required("user", self.user)?;
+        if !user.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(ConfigError::Invalid("user", format!(
+                "\"{}\" is not a valid reddit username", user)));
+        }
+
+        let auth = match self.auth.as_deref().unwrap_or("password")
+        {
+            "password" => Auth::Password(required("pass", self.pass)?),
+            "refresh_token" =>
+            {
+                let uri = self.redirect_uri.unwrap_or_else(||
+                    "http://127.0.0.1:65010/authorize_callback".to_owned());
+                let redirect_uri = Url::parse(&uri)
+                    .map_err(|e| ConfigError::Invalid("redirect_uri", format!(
+                        "\"{}\" is not a valid URL: {}", uri, e)))?;
+
+                // the authorize command has to be able to listen on it
+                let loopback = matches!(redirect_uri.host_str(),
+                    Some("localhost") | Some("127.0.0.1") | Some("[::1]"));
+                if redirect_uri.scheme() != "http" || !loopback
+                {
+                    return Err(ConfigError::Invalid("redirect_uri", format!(
+                        "\"{}\" is not an http URL on localhost", uri)));
+                }
+
+                Auth::RefreshToken { redirect_uri }
+            },
+            other => return Err(ConfigError::Invalid("auth", format!(
+                "\"{}\" is not \"password\" or \"refresh_token\"", other))),
+        };
+
+        // installed apps don't have a secret
+        let secret = match auth
+        {
+            Auth::Password(_) => required("secret", self.secret)?,
+            Auth::RefreshToken { .. } => self.secret.unwrap_or_default(),
+        };
+
+        Ok(Config
+        {
+            id: required("id", self.id)?,
+            secret,
+            user,
+            auth,
+            maintainer: required("maintainer", self.maintainer)?,
+            subreddits,
+            wiki: Wiki
+            {
+                api,
+                article_path: wiki.article_path,
+                disambiguation_category: wiki.disambiguation_category.map(|c|
+                    if c.starts_with("Category:") { c } else { format!("Category:{}", c) }),
+                thumb_width: wiki.thumb_width,
+                only_images: wiki.only_images,
+            },
+            state_dir: self.state_dir.unwrap_or_else(|| PathBuf::from("state")),
+            retry: self.retry,
+            cache: self.cache,
+            info_fields: self.info_fields.unwrap_or_else(|| vec![
+                InfoField::new("scientific_name", "Species"),
+                InfoField::new("first_appearance", "First appearance"),
+                InfoField::new("voice_actor", "Voice actor"),
+            ]),
+            medias,
+        })
+    }
+}
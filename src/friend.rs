@@ -5,18 +5,22 @@ use uuid::Uuid;
 /// Representation of a friend is split into two parts,
 /// linked by a UUID. This part is the friend name parsed from messages
 /// to the bot.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Friend
 {
     /// The name of the friend (formatted)
     ///
     /// name formatting will capitalize the first letter of every word.
     pub name: String,
-    /// The media the friend appears in
+    /// The media the friend appears in, if one was asked for
     ///
     /// Some friends don't appear in some medias, if so, there will be
     /// no wiki page associated with this friend.
-    pub media: Media,
+    pub media: Option<Media>,
+    /// The media that was asked for, if it isn't one of the known medias
+    ///
+    /// The friend is looked up without a media instead.
+    pub unknown_media: Option<String>,
     /// The wiki title for this friend
     ///
     /// This is a guess at what the title would be, the actual wiki title may
@@ -36,7 +40,7 @@ pub struct Friend
 
 impl Friend
 {
-    pub fn new<S: AsRef<str>>(source: &S, id: Uuid) -> Friend
+    pub fn new<S: AsRef<str>>(source: &S, id: Uuid, medias: &[Media]) -> Friend
     {
         // This could probably be done with a simple regex, or nom.
         // But I don't know how.
@@ -49,7 +53,8 @@ impl Friend
             return Friend
             {
                 name: name.clone(),
-                media: Media::None,
+                media: None,
+                unknown_media: None,
                 title: name,
                 id,
             }
@@ -59,7 +64,7 @@ impl Friend
         let mut media = String::new();
 
         let mut chars = source.chars();
-        while let Some(c) = chars.next()
+        for c in chars.by_ref()
         {
             if c == '/'
             {
@@ -77,13 +82,30 @@ impl Friend
         }
 
         let name = Friend::fmt_name(name.trim());
-        let media = Media::new(&media);
-        let title = format!("{}{}", name, media.wiki_suffix());
+        let media = media.trim();
+        let (media, unknown_media) = if media.is_empty()
+        {
+            (None, None)
+        }
+        else
+        {
+            match Media::find(&media, medias)
+            {
+                Some(found) => (Some(found), None),
+                None => (None, Some(media.to_owned())),
+            }
+        };
+        let title = match media
+        {
+            Some(ref media) => format!("{}{}", name, media.suffix),
+            None => name.clone(),
+        };
 
         Friend
         {
             name,
             media,
+            unknown_media,
             title,
             id,
         }
@@ -132,7 +154,7 @@ impl Friend
 
     /// Searches for a username mention in a source, and parses out
     /// quoted friends
-    pub fn find(source: &str, target_user: &str) -> Option<Vec<Friend>>
+    pub fn find(source: &str, target_user: &str, medias: &[Media]) -> Option<Vec<Friend>>
     {
         // allowed characters in reddit usernames
         static USERNAME_CHARS: &[char] = &[
@@ -154,7 +176,7 @@ impl Friend
                 if let Some('/') = chars.next()
                 {
                     let mut user = String::new();
-                    while let Some(c) = chars.next()
+                    for c in chars.by_ref()
                     {
                         if USERNAME_CHARS.contains(&c)
                         {
@@ -179,7 +201,7 @@ impl Friend
                             if c == '"'
                             {
                                 let mut quoted = String::new();
-                                while let Some(c) = chars.next()
+                                for c in chars.by_ref()
                                 {
                                     if c == '"'
                                     {
@@ -191,7 +213,7 @@ impl Friend
                                     }
                                 }
 
-                                friends.push(Friend::new(&quoted, Uuid::new_v4()));
+                                friends.push(Friend::new(&quoted, Uuid::new_v4(), medias));
                             }
                             else if !c.is_whitespace()
                             {
@@ -209,66 +231,70 @@ impl Friend
     }
 }
 
-/// A media a friend might appear in.
+/// A media a friend might appear in
 ///
-/// Parsing of a media is forgiving, and falls back to none.
-#[derive(Debug, Copy, Clone)]
-pub enum Media
+/// The medias are set in the config file, so new ones can be added without a
+/// new release.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Media
 {
-    /// No media specified, or the media could not be parsed
-    None,
-    /// The 2017 anime (just season one)
-    Anime,
-    /// The 2019 second season of the anime
-    Season2,
-    /// All manga adaptations
-    Manga,
-    /// The 2018 game Kemono Friends Festival
-    Festival,
-    /// The pavilion game
-    Pavilion,
-    /// The 2019 game Kemono Friends 3 (probably both the phone and arcade version)
-    KF3,
-    /// The nexon game
-    Nexon,
-    /// All stage adaptations
-    Stage,
-    
+    /// The name of the media, which users can write to ask for it
+    pub name: String,
+    /// Other ways users might write the media
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// The wiki suffix for the media (includes the slash), like "/Anime"
+    pub suffix: String,
+    /// How the media is written in replies, which users can also write
+    pub label: String,
 }
 
 impl Media
 {
-    /// Parse a new Media from a string
-    pub fn new<S: AsRef<str>>(source: &S) -> Media
+    /// Find a media by its name, label or one of its aliases, ignoring case
+    pub fn find<S: AsRef<str>>(source: &S, medias: &[Media]) -> Option<Media>
     {
-        match source.as_ref().trim().to_lowercase().as_str()
-        {
-            "anime" | "season 1"=>        Media::Anime,
-            "season 2" =>                 Media::Season2,
-            "manga" =>                    Media::Manga,
-            "festival" =>                 Media::Festival,
-            "pavilion" =>                 Media::Pavilion,
-            "kf3" | "kemono friends 3" => Media::KF3,
-            "nexon" | "nexon game" =>     Media::Nexon,
-            "stage" | "stage play" =>     Media::Stage,
-            _ =>                          Media::None,
-        }
+        let source = source.as_ref().trim().to_lowercase();
+
+        medias.iter()
+            .find(|media| media.name.to_lowercase() == source ||
+                media.label.to_lowercase() == source ||
+                media.aliases.iter().any(|alias| alias.to_lowercase() == source))
+            .cloned()
     }
-    
-    /// Format the media into it's wiki suffix (includes the slash)
-    pub fn wiki_suffix(self) -> &'static str
+
+    /// The medias that are known without any configuration
+    pub fn defaults() -> Vec<Media>
+    {
+        vec![
+            // The 2017 anime (just season one)
+            Media::new("Anime", &["season 1"], "/Anime", "Anime"),
+            // The 2019 second season of the anime
+            Media::new("Season 2", &[], "/Season_2", "Season 2"),
+            // All manga adaptations
+            Media::new("Manga", &[], "/Manga", "Manga"),
+            // The 2018 game Kemono Friends Festival
+            Media::new("Festival", &[], "/Festival", "Festival"),
+            // The pavilion game
+            Media::new("Pavilion", &[], "/Pavilion", "Pavilion"),
+            // The 2019 game Kemono Friends 3 (probably both the phone and arcade version)
+            Media::new("KF3", &["kemono friends 3"], "/KF3", "Kemono Friends 3"),
+            // The nexon game
+            Media::new("Nexon", &["nexon game"], "/Nexon Game", "Nexon Game"),
+            // All stage adaptations
+            Media::new("Stage", &["stage play"], "/Stage Play", "Stage Play"),
+        ]
+    }
+
+    fn new(name: &str, aliases: &[&str], suffix: &str, label: &str) -> Media
     {
-        match self
+        Media
         {
-            Media::None => "",
-            Media::Anime => "/Anime",
-            Media::Season2 => "/Season_2",
-            Media::Manga => "/Manga",
-            Media::Festival => "/Festival",
-            Media::Pavilion => "/Pavilion",
-            Media::KF3 => "/KF3",
-            Media::Nexon => "/Nexon Game",
-            Media::Stage => "/Stage Play",
+            name: name.to_owned(),
+            aliases: aliases.iter().map(|a| (*a).to_owned()).collect(),
+            suffix: suffix.to_owned(),
+            label: label.to_owned(),
         }
     }
 }
@@ -0,0 +1,438 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use reddit::Message;
+use retry::Backoff;
+use state::{self, now};
+
+/// How far along a message is
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status
+{
+    /// The message has been read from the inbox, but nothing else
+    Received,
+    /// The wiki pages for the message have been found
+    LookedUp,
+    /// A reply is being sent
+    ///
+    /// If the bot stops in this state, it can't know if the reply made it,
+    /// so the message is abandoned. A reply that fails while the bot is
+    /// running is retried as usual.
+    Replying,
+    /// The reply was refused by reddit's ratelimit, and is waiting to be sent again
+    ///
+    /// The reply itself is only kept in memory, so if the bot stops in this
+    /// state, the message is looked up again.
+    Held,
+    /// The reply was sent
+    Replied,
+    /// Something went wrong, and the message should be tried again
+    Failed,
+    /// Something went wrong too many times, or in a way that can't be fixed
+    Abandoned,
+    /// The message doesn't need a reply
+    Ignored,
+}
+
+impl Status
+{
+    /// Check if a message in this state still needs work
+    pub fn pending(self) -> bool
+    {
+        match self
+        {
+            Status::Received | Status::LookedUp | Status::Failed => true,
+            Status::Replying | Status::Held | Status::Replied | Status::Abandoned | Status::Ignored
+                => false,
+        }
+    }
+
+    /// Check if a message in this state is done with, and won't change again
+    pub fn finished(self) -> bool
+    {
+        match self
+        {
+            Status::Replied | Status::Abandoned | Status::Ignored => true,
+            Status::Received | Status::LookedUp | Status::Replying | Status::Held | Status::Failed
+                => false,
+        }
+    }
+
+    // a message is kept until it is finished, since a held or failed reply
+    // needs it to be tried again
+    fn keeps_message(self) -> bool
+    {
+        !self.finished()
+    }
+}
+
+/// A persistent record of every message the bot has handled recently
+///
+/// The journal is an append-only file of JSON lines, one line per status change.
+/// It is compacted every time it is opened, and whenever `forget_finished`
+/// drops old messages.
+#[derive(Debug)]
+pub struct Journal
+{
+    path: PathBuf,
+    file: File,
+    entries: HashMap<String, Entry>,
+}
+
+impl Journal
+{
+    /// Open a journal, creating it if it does not exist
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Journal>
+    {
+        let path = path.as_ref();
+
+        if let Some(dir) = path.parent()
+        {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut entries = match File::open(path)
+        {
+            Ok(file) => Journal::replay(file)?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+
+        for (name, entry) in entries.iter_mut()
+        {
+            if entry.status == Status::Replying
+            {
+                warn!("Message {} may or may not have been replied to, it will not be retried",
+                    name);
+                entry.status = Status::Abandoned;
+                entry.message = None;
+            }
+            else if entry.status == Status::Held
+            {
+                info!("Message {} was waiting on a ratelimit, it will be looked up again", name);
+                entry.status = Status::Received;
+            }
+        }
+
+        Journal::forget(&mut entries);
+        let file = Journal::compact(path, &entries)?;
+
+        Ok(Journal
+        {
+            path: path.to_owned(),
+            file,
+            entries,
+        })
+    }
+
+    /// Drop messages that were finished with long ago, and compact the journal
+    /// if there were any
+    ///
+    /// A finished message only needs to be remembered until it is marked read,
+    /// so that it isn't handled again if it comes back in the inbox.
+    pub fn forget_finished(&mut self) -> io::Result<()>
+    {
+        if Journal::forget(&mut self.entries) > 0
+        {
+            self.file = Journal::compact(&self.path, &self.entries)?;
+        }
+
+        Ok(())
+    }
+
+    /// Get the status of a message, if it is in the journal
+    pub fn status(&self, name: &str) -> Option<Status>
+    {
+        self.entries.get(name).map(|e| e.status)
+    }
+
+    /// Add a new message to the journal
+    pub fn add(&mut self, message: &Message, status: Status) -> io::Result<()>
+    {
+        let name = message.name.clone();
+        let message = if status.keeps_message() { Some(message.clone()) } else { None };
+
+        self.write(&Record
+        {
+            name: name.clone(),
+            status,
+            message: message.clone(),
+            attempts: None,
+            retry_at: None,
+            at: Some(now()),
+        })?;
+
+        self.entries.insert(name, Entry
+        {
+            status,
+            message,
+            attempts: 0,
+            retry_at: None,
+            updated: now(),
+        });
+
+        Ok(())
+    }
+
+    /// Update the status of a message already in the journal
+    pub fn set(&mut self, name: &str, status: Status) -> io::Result<()>
+    {
+        self.write(&Record
+        {
+            name: name.to_owned(),
+            status,
+            message: None,
+            attempts: None,
+            retry_at: None,
+            at: Some(now()),
+        })?;
+
+        if let Some(entry) = self.entries.get_mut(name)
+        {
+            entry.status = status;
+            entry.updated = now();
+            if !status.keeps_message()
+            {
+                entry.message = None;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Hold a message whose reply has to wait for reddit's ratelimit
+    ///
+    /// This doesn't count as a failed attempt.
+    pub fn hold(&mut self, message: &Message) -> io::Result<()>
+    {
+        self.write(&Record
+        {
+            name: message.name.clone(),
+            status: Status::Held,
+            message: Some(message.clone()),
+            attempts: None,
+            retry_at: None,
+            at: Some(now()),
+        })?;
+
+        if let Some(entry) = self.entries.get_mut(&message.name)
+        {
+            entry.status = Status::Held;
+            entry.message = Some(message.clone());
+            entry.updated = now();
+        }
+
+        Ok(())
+    }
+
+    /// Record a failed attempt at a message
+    ///
+    /// The message will be tried again after a delay, unless the error can't be
+    /// retried, or the message has run out of attempts. Returns the message's
+    /// new status.
+    pub fn fail(&mut self, name: &str, backoff: &Backoff, retryable: bool) -> io::Result<Status>
+    {
+        let attempts = match self.entries.get(name)
+        {
+            Some(entry) => entry.attempts + 1,
+            None => 1,
+        };
+
+        let (status, retry_at) = if !retryable
+        {
+            error!("Giving up on message {}, the error can't be retried", name);
+            (Status::Abandoned, None)
+        }
+        else if attempts >= backoff.max_attempts
+        {
+            error!("Giving up on message {} after {} attempts", name, attempts);
+            (Status::Abandoned, None)
+        }
+        else
+        {
+            let delay = backoff.delay(attempts);
+            info!("Retrying message {} in {} seconds (attempt {} of {})",
+                name, delay.as_secs(), attempts + 1, backoff.max_attempts);
+            (Status::Failed, Some(now() + delay.as_secs()))
+        };
+
+        self.write(&Record
+        {
+            name: name.to_owned(),
+            status,
+            message: None,
+            attempts: Some(attempts),
+            retry_at,
+            at: Some(now()),
+        })?;
+
+        if let Some(entry) = self.entries.get_mut(name)
+        {
+            entry.status = status;
+            entry.attempts = attempts;
+            entry.retry_at = retry_at;
+            entry.updated = now();
+            if !status.keeps_message()
+            {
+                entry.message = None;
+            }
+        }
+
+        Ok(status)
+    }
+
+    /// Get every message that still needs work, and isn't waiting to be retried
+    pub fn pending(&self) -> Vec<Message>
+    {
+        let now = now();
+
+        self.entries.values()
+            .filter(|e| e.status.pending())
+            .filter(|e| e.retry_at.map(|at| at <= now).unwrap_or(true))
+            .filter_map(|e| e.message.clone())
+            .collect()
+    }
+
+    fn write(&mut self, record: &Record) -> io::Result<()>
+    {
+        let mut line = ::serde_json::to_string(record)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        line.push('\n');
+
+        self.file.write_all(line.as_bytes())?;
+        self.file.sync_data()
+    }
+
+    fn replay(file: File) -> io::Result<HashMap<String, Entry>>
+    {
+        let mut entries: HashMap<String, Entry> = HashMap::new();
+
+        for line in BufReader::new(file).lines()
+        {
+            let line = line?;
+            if line.trim().is_empty()
+            {
+                continue;
+            }
+
+            // a line can be cut short if the bot stopped while writing it
+            let record = match ::serde_json::from_str::<Record>(&line)
+            {
+                Ok(record) => record,
+                Err(e) =>
+                {
+                    warn!("Skipping bad journal line \"{}\": {}", line, e);
+                    continue;
+                }
+            };
+
+            let entry = entries.entry(record.name).or_insert(Entry
+            {
+                status: record.status,
+                message: None,
+                attempts: 0,
+                retry_at: None,
+                updated: 0,
+            });
+
+            entry.status = record.status;
+            entry.retry_at = record.retry_at;
+            // journals from before records had a time are kept for a while longer
+            entry.updated = record.at.unwrap_or_else(now);
+            if let Some(attempts) = record.attempts
+            {
+                entry.attempts = attempts;
+            }
+            if record.message.is_some()
+            {
+                entry.message = record.message;
+            }
+            if !entry.status.keeps_message()
+            {
+                entry.message = None;
+            }
+        }
+
+        Ok(entries)
+    }
+
+    // drop messages that were finished before `FORGET_AFTER`, returning how many
+    fn forget(entries: &mut HashMap<String, Entry>) -> usize
+    {
+        let before = entries.len();
+        let cutoff = now().saturating_sub(FORGET_AFTER);
+
+        entries.retain(|_, entry| !entry.status.finished() || entry.updated > cutoff);
+
+        before - entries.len()
+    }
+
+    // write a fresh journal with one line per message, and swap it in
+    fn compact(path: &Path, entries: &HashMap<String, Entry>) -> io::Result<File>
+    {
+        state::replace(path, |file|
+        {
+            for (name, entry) in entries.iter()
+            {
+                let record = Record
+                {
+                    name: name.clone(),
+                    status: entry.status,
+                    message: entry.message.clone(),
+                    attempts: if entry.attempts > 0 { Some(entry.attempts) } else { None },
+                    retry_at: entry.retry_at,
+                    at: Some(entry.updated),
+                };
+                let line = ::serde_json::to_string(&record)
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                writeln!(file, "{}", line)?;
+            }
+
+            Ok(())
+        })?;
+
+        OpenOptions::new()
+            .append(true)
+            .open(path)
+    }
+}
+
+#[derive(Debug)]
+struct Entry
+{
+    status: Status,
+    // only kept while the message still needs work
+    message: Option<Message>,
+    // failed attempts so far
+    attempts: u32,
+    // when a failed message can be tried again, in seconds since the epoch
+    retry_at: Option<u64>,
+    // when the status last changed, in seconds since the epoch
+    updated: u64,
+}
+
+// how long a finished message is remembered, in seconds. A message that
+// couldn't be marked read comes back well before this
+const FORGET_AFTER: u64 = 7 * 24 * 60 * 60;
+
+// ==============================
+// Serde structs below
+// ==============================
+
+#[derive(Serialize, Deserialize)]
+struct Record
+{
+    name: String,
+    status: Status,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    message: Option<Message>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    attempts: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    retry_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    at: Option<u64>,
+}
@@ -13,11 +13,14 @@ use simplelog::*;
 mod friend;
 mod page;
 mod reddit;
-mod secrets;
+mod config;
+mod journal;
+mod retry;
 mod process;
+mod state;
 
 /// The current version
-pub static VERSION: &'static str = "1.2";
+pub static VERSION: &str = "1.2";
 
 fn main()
 {
@@ -42,6 +45,18 @@ fn main()
              .value_name("INTERVAL")
              .required(true)
              .help("The interval, in seconds, to run the program loop on"))
+        .arg(clap::Arg::with_name("config")
+             .short("c")
+             .long("config")
+             .takes_value(true)
+             .value_name("CONFIG")
+             .required(false)
+             .help("The JSON config file. Fields can be overridden with LIBRARIAN_* variables"))
+        .setting(clap::AppSettings::SubcommandsNegateReqs)
+        .subcommand(clap::SubCommand::with_name("index")
+             .about("Downloads every page title from the wiki, to check friend titles against"))
+        .subcommand(clap::SubCommand::with_name("authorize")
+             .about("Authorizes the bot's reddit account, for the refresh_token auth mode"))
         .get_matches();
 
     if let Some(logfile) = matches.value_of("logfile")
@@ -59,22 +74,78 @@ fn main()
         SimpleLogger::init(LogLevelFilter::Info, Config::default()).unwrap();
     }
 
+    let config = match config::Config::load(matches.value_of("config"))
+    {
+        Ok(config) => config,
+        Err(e) =>
+        {
+            error!("Could not load configuration: {}", e);
+            ::std::process::exit(1);
+        }
+    };
+
+    if matches.subcommand_matches("index").is_some()
+    {
+        build_index(&config);
+        return;
+    }
+
+    if matches.subcommand_matches("authorize").is_some()
+    {
+        authorize(&config);
+        return;
+    }
+
     let interval = Duration::from_secs(matches.value_of("interval")
         .unwrap()
         .parse::<u64>()
         .unwrap());
 
     let client = reqwest::blocking::Client::new();
-    let mut session = reddit::Session::new(
-        secrets::id(),
-        secrets::secret(),
-        secrets::user(),
-        secrets::pass());
+    let session = reddit::Session::new(&config);
+    let wiki = page::WikiClient::new(&config);
+
+    let journal = match journal::Journal::open(config.state_dir.join("journal.jsonl"))
+    {
+        Ok(journal) => journal,
+        Err(e) =>
+        {
+            error!("Could not open the journal: {}", e);
+            ::std::process::exit(1);
+        }
+    };
+
+    let cache = page::cache::PageCache::open(config.state_dir.join("cache.json"), config.cache);
+
+    let index = match page::index::TitleIndex::load(config.state_dir.join("index.json"))
+    {
+        Ok(index) =>
+        {
+            let (titles, redirects) = index.size();
+            info!("Loaded an index of {} titles and {} redirects", titles, redirects);
+            Some(index)
+        },
+        Err(ref e) if e.kind() == ::std::io::ErrorKind::NotFound => None,
+        Err(e) =>
+        {
+            warn!("Could not load the title index, titles will not be checked: {}", e);
+            None
+        },
+    };
+
+    let mut bot = Bot
+    {
+        session,
+        queue: reddit::ReplyQueue::new(),
+        cache,
+        index,
+        journal,
+    };
 
     loop
     {
         let start = Instant::now();
-        cycle(&client, &mut session);
+        cycle(&client, &wiki, &mut bot, &config);
 
         let now = Instant::now();
         if start + interval > now
@@ -88,153 +159,354 @@ fn main()
 
 }
 
+/// What the bot keeps from one loop to the next
+struct Bot
+{
+    /// The reddit authorization and ratelimit
+    session: reddit::Session,
+    /// Replies waiting on reddit's ratelimit
+    queue: reddit::ReplyQueue,
+    /// Pages that were looked up recently
+    cache: page::cache::PageCache,
+    /// Every title on the wiki, if the `index` command has been run
+    index: Option<page::index::TitleIndex>,
+    /// Every message handled recently
+    journal: journal::Journal,
+}
+
 // Todo: replace unrwaps with something more graceful
-fn cycle(client: &reqwest::blocking::Client, session: &mut reddit::Session)
+fn cycle(
+    client: &reqwest::blocking::Client,
+    wiki: &page::WikiClient,
+    bot: &mut Bot,
+    config: &config::Config)
 {
     use reddit::*;
     use process::*;
-    use page::{Page, WikiError, partial_page, image_url};
+    use journal::Status;
+    use retry::Retryable;
 
-    let messages = match Message::get_unread(client, session, None)
+    // replies held by the ratelimit go first, they've waited long enough
+    let ready = bot.queue.take_ready();
+    if !ready.is_empty()
     {
-        Ok(ref m) if m.len() == 0 =>
+        info!("Sending {} held replies", ready.len());
+        if send_replies(client, bot, config, ready).is_none()
         {
-            info!("No unread messages");
             return;
         }
-        Ok(m) =>
-        {
-            info!("Recieved {} unread messages", m.len());
-            m
-        },
+    }
+
+    let unread = match Message::get_unread(client, &mut bot.session, None)
+    {
+        Ok(m) => m,
         Err(e) =>
         {
-            match e
-            {
-                RedditError::Unauthorized => error!(
-                    "Bad reddit authorization while getting messages"),
-                RedditError::BadCredentials => error!(
-                    "Bad reddit credentials while getting messages"),
-                RedditError::OtherStatus(code) => warn!( // usually a 503 or something
-                    "Other status code {:?} while getting messages", code),
-                RedditError::OtherError(err) => error!(
-                    "Other error \"{:?}\" while getting messages", err),
-            }
+            log_reddit_error(e, "getting messages");
             return;
         }
     };
 
-    match Message::mark_read(client, session, &messages)
+    if unread.is_empty()
     {
-        Ok(_) => info!("Marked messages as read"),
-        Err(e) =>
+        info!("No unread messages");
+    }
+    else
+    {
+        info!("Recieved {} unread messages", unread.len());
+
+        // journal new messages before marking them read, so they can't be lost
+        for msg in unread.iter()
         {
-            match e
+            if bot.journal.status(&msg.name).is_some()
             {
-                RedditError::Unauthorized => error!(
-                    "Bad reddit authorization while marking messages"),
-                RedditError::BadCredentials => error!(
-                    "Bad reddit credentials while marking messages"),
-                RedditError::OtherStatus(code) => warn!( // usually a 503 or something
-                    "Other status code {:?} while marking messages", code),
-                RedditError::OtherError(err) => error!(
-                    "Other error \"{:?}\" while marking messages", err),
+                continue; // handled before, but not marked read
             }
-            return;
+
+            let status = if config.subreddits.allows(&msg.subreddit)
+            {
+                Status::Received
+            }
+            else
+            {
+                Status::Ignored
+            };
+
+            if journaled(bot.journal.add(msg, status)).is_none()
+            {
+                return;
+            }
+        }
+
+        match Message::mark_read(client, &mut bot.session, &unread)
+        {
+            Ok(_) => info!("Marked messages as read"),
+            // the journal has them now, so they will be skipped if they show up again
+            Err(e) => log_reddit_error(e, "marking messages"),
         }
     }
 
-    let messages = filter_messages(messages);
+    if let Err(e) = bot.journal.forget_finished()
+    {
+        warn!("Could not compact the journal: {}", e);
+    }
+
+    let messages = bot.journal.pending();
+
+    if messages.is_empty()
+    {
+        info!("No messages to process");
+        return;
+    }
+
+    info!("Processing {} messages", messages.len());
 
-    info!("Filtered to {} messages", messages.len());
+    let names = messages.iter()
+        .map(|m| m.name.clone())
+        .collect::<Vec<_>>();
 
-    let (pairs, friends) = find_friends(messages, secrets::user());
+    let requests = find_friends(messages, &config.user, &config.medias, bot.index.as_ref());
 
-    if friends.len() == 0
+    // messages that don't ask for any friends don't get a reply
+    for name in names.iter()
+        .filter(|name| !requests.iter().any(|(msg, _)| &&msg.name == name))
+    {
+        if journaled(bot.journal.set(name, Status::Ignored)).is_none()
+        {
+            return;
+        }
+    }
+
+    if requests.is_empty()
     {
         info!("No friends found");
         return;
     }
 
-    info!("Parsed a total {} friends", friends.len());
+    info!("Parsed a total {} friends", requests.iter()
+        .map(|(_, friends)| friends.len())
+        .sum::<usize>());
+
+    let results = find_pages(client, wiki, &mut bot.cache, bot.index.as_ref(), &requests);
 
-    let partials = match partial_page::PartialPage::get(client, &friends)
+    let mut replies = Vec::new();
+    for ((msg, friends), result) in requests.into_iter().zip(results)
     {
-        Ok(p) => p,
-        Err(e) =>
+        match result
         {
-            match e
+            Ok(lookup) =>
             {
-                WikiError::StatusError(code) => warn!(
-                    "Other status code {:?} while getting partial pages", code),
-                WikiError::RequestError(err) => error!(
-                    "Other error \"{:?}\" while getting partial pages", err),
-            }
-            return;
+                if journaled(bot.journal.set(&msg.name, Status::LookedUp)).is_none()
+                {
+                    return;
+                }
+
+                let body = make_reply(&msg, &friends, &lookup, config);
+                replies.push((msg, body));
+            },
+            Err(e) =>
+            {
+                let retryable = e.retryable();
+                log_wiki_error(e, &format!("looking up message {}", msg.name));
+
+                match journaled(bot.journal.fail(&msg.name, &config.retry, retryable))
+                {
+                    // let them know, rather than leaving them with nothing
+                    Some(Status::Abandoned) => replies.push((msg, make_failed_reply(config))),
+                    Some(_) => {},
+                    None => return,
+                }
+            },
         }
-    };
+    }
 
-    let images = match image_url::ImageUrl::get(client, &partials)
+    send_replies(client, bot, config, replies);
+}
+
+// send replies, and hold any that reddit's ratelimit refuses
+//
+// Returns `None` if the journal couldn't be written.
+fn send_replies(
+    client: &reqwest::blocking::Client,
+    bot: &mut Bot,
+    config: &config::Config,
+    replies: Vec<(reddit::Message, String)>)
+    -> Option<()>
+{
+    use std::time::Duration;
+    use reddit::RedditError;
+    use journal::Status;
+    use retry::Retryable;
+
+    // don't bother reddit while the account is still ratelimited
+    if bot.queue.waiting()
     {
-        Ok(i) => i,
-        Err(e) =>
+        for (msg, body) in replies
+        {
+            journaled(bot.journal.hold(&msg))?;
+            bot.queue.hold(msg, body, Duration::from_secs(0));
+        }
+        return Some(());
+    }
+
+    // each reply is marked just before it's sent, so a crash only leaves that one in doubt
+    let journal = &mut bot.journal;
+    let mut journal_failed = false;
+    let outcomes = reddit::reply(client, &mut bot.session, replies.iter()
+            .map(|(msg, body)| (msg.name.clone(), body.clone()))
+            .collect(),
+        |name|
+        {
+            journal_failed = journaled(journal.set(name, Status::Replying)).is_none();
+            !journal_failed
+        });
+
+    for ((msg, body), (_, outcome)) in replies.into_iter().zip(outcomes)
+    {
+        let result = match outcome
         {
-            match e
+            Ok(_) =>
             {
-                WikiError::StatusError(code) => warn!(
-                    "Other status code {:?} while getting images", code),
-                WikiError::RequestError(err) => error!(
-                    "Other error \"{:?}\" while getting images", err)
-            }
-            return;
+                info!("Replied to message {}", msg.name);
+                bot.journal.set(&msg.name, Status::Replied)
+            },
+            Err(RedditError::RateLimit { message, wait }) =>
+            {
+                // reddit's wording changes now and then, so guess if it can't be read
+                let wait = wait.unwrap_or_else(||
+                {
+                    warn!("Could not read the ratelimit wait from \"{}\"", message);
+                    Duration::from_secs(config.retry.base_delay)
+                });
+                info!("Holding the reply to message {} for {} seconds",
+                    msg.name, wait.as_secs());
+                let result = bot.journal.hold(&msg);
+                bot.queue.hold(msg, body, wait);
+                result
+            },
+            Err(e) =>
+            {
+                let retryable = e.retryable();
+                log_reddit_error(e, &format!("replying to message {}", msg.name));
+                bot.journal.fail(&msg.name, &config.retry, retryable).map(|_| ())
+            },
+        };
+
+        journaled(result)?;
+    }
+
+    if journal_failed { None } else { Some(()) }
+}
+
+/// Log a journal write error
+///
+/// Returns `None` if the write failed. Nothing else should be done for a
+/// message that couldn't be journaled, otherwise it might get two replies.
+fn journaled<T>(result: ::std::io::Result<T>) -> Option<T>
+{
+    match result
+    {
+        Ok(t) => Some(t),
+        Err(e) =>
+        {
+            error!("Could not write to the journal: {}", e);
+            None
         }
-    };
+    }
+}
 
-    let pages = Page::make(partials, &images, &friends);
+fn log_reddit_error(err: reddit::RedditError, action: &str)
+{
+    use reddit::RedditError;
 
-    info!("Made {} pages", pages.len()); // due to wiki wierdness, it always makes at least one
+    match err
+    {
+        RedditError::Unauthorized => error!(
+            "Bad reddit authorization while {}", action),
+        RedditError::BadCredentials => error!(
+            "Bad reddit credentials while {}", action),
+        RedditError::NoRefreshToken(None) => error!(
+            "No refresh token while {}, run the authorize command", action),
+        RedditError::NoRefreshToken(Some(err)) => error!(
+            "Could not read the refresh token while {}, run the authorize command: {}",
+            action, err),
+        RedditError::OtherStatus(code) => warn!( // usually a 503 or something
+            "Other status code {:?} while {}", code, action),
+        RedditError::OtherError(err) => error!(
+            "Other error \"{:?}\" while {}", err, action),
+        RedditError::RateLimit { message, .. } => warn!(
+            "Ratelimited while {}: {}", action, message),
+        RedditError::ThreadLocked => info!(
+            "The thread was locked while {}", action),
+        RedditError::DeletedComment => info!(
+            "The comment was deleted while {}", action),
+        RedditError::TooOld => info!(
+            "The thread was archived while {}", action),
+        RedditError::ApiError { code, message } => error!(
+            "Reddit refused a request while {}: {} ({})", action, message, code),
+    }
+}
 
-    let replies = make_replies(pairs, pages);
+// download the title index and save it in the state directory
+fn build_index(config: &config::Config)
+{
+    let client = reqwest::blocking::Client::new();
+    let wiki = page::WikiClient::new(config);
+    let path = config.state_dir.join("index.json");
 
-    match reply(client, session, replies)
+    let index = match page::index::TitleIndex::build(&client, &wiki)
     {
-        Ok(_) => info!("Replied to messages"),
-        Err(e) => match e
+        Ok(index) => index,
+        Err(e) =>
         {
-            RedditError::Unauthorized => error!(
-                "Bad reddit authorization while replying to messages"),
-            RedditError::BadCredentials => error!(
-                "Bad reddit credentials while replying to messages"),
-            RedditError::OtherStatus(code) => warn!( // usually a 503 or something
-                "Other status code {:?} while replying to messages", code),
-            RedditError::OtherError(err) => error!(
-                "Other error \"{:?}\" while replying to messages", err),
+            log_wiki_error(e, "building the title index");
+            ::std::process::exit(1);
         }
     };
+
+    if let Err(e) = index.save(&path)
+    {
+        error!("Could not save the title index to {}: {}", path.display(), e);
+        ::std::process::exit(1);
+    }
+
+    let (titles, redirects) = index.size();
+    info!("Saved {} titles and {} redirects to {}", titles, redirects, path.display());
 }
 
-fn filter_messages(messages: Vec<reddit::Message>) -> Vec<reddit::Message>
+// authorize the bot's account and save the refresh token in the state directory
+fn authorize(config: &config::Config)
 {
-    // subreddit whitelist
-    let whitelist = &[
-        "kemonofriends",
-        "japari_librarian"
-    ];
+    let client = reqwest::blocking::Client::new();
 
-    let mut filtered = Vec::with_capacity(messages.len());
-    for msg in messages
+    match reddit::authorize(&client, config)
     {
-        // allow any private message, or any comment from the whitelisted subreddits
-        if let &Some(ref sub) = &msg.subreddit
+        Ok(path) => info!("Saved the refresh token to {}", path.display()),
+        Err(e) =>
         {
-            if !whitelist.contains(&sub.to_lowercase().as_str())
-            {
-                continue;
-            }
+            error!("Could not authorize the bot: {}", e);
+            ::std::process::exit(1);
         }
-
-        filtered.push(msg);
     }
+}
 
-    filtered
+fn log_wiki_error(err: page::WikiError, action: &str)
+{
+    use page::WikiError;
+
+    match err
+    {
+        WikiError::StatusError(code) => warn!(
+            "Other status code {:?} while {}", code, action),
+        WikiError::RequestError(err) => error!(
+            "Other error \"{:?}\" while {}", err, action),
+        WikiError::Parse(err) => error!(
+            "Unexpected response from the wiki while {}: {}", action, err),
+        WikiError::ApiError { code, info } => error!(
+            "The wiki refused a request while {}: {} ({})", action, info, code),
+        WikiError::ApiWarning { code, info } => error!(
+            "The wiki ignored part of a request while {}: {} ({})", action, info, code),
+        WikiError::Missing(field) => error!(
+            "The wiki left out \"{}\" while {}", field, action),
+    }
 }
@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use friend::Friend;
+use state::{self, now};
+use super::Page;
+use super::suggestion::Suggestion;
+
+/// How long looked up pages are kept
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheSettings
+{
+    /// How long a found page is kept, in seconds. 0 turns the cache off
+    pub ttl: u64,
+    /// How long a title with no page is remembered, in seconds
+    pub missing_ttl: u64,
+    /// Whether the cache is saved in the state directory, to outlive restarts
+    pub persist: bool,
+}
+
+impl Default for CacheSettings
+{
+    fn default() -> CacheSettings
+    {
+        CacheSettings
+        {
+            ttl: 60 * 60,
+            missing_ttl: 10 * 60,
+            persist: true,
+        }
+    }
+}
+
+/// Pages that have already been looked up, by the title that was asked for
+///
+/// Titles with no page are cached too, along with the suggestions found for
+/// them, so that a popular typo doesn't go to the wiki every time.
+#[derive(Debug)]
+pub struct PageCache
+{
+    path: Option<PathBuf>,
+    settings: CacheSettings,
+    entries: HashMap<String, Entry>,
+}
+
+impl PageCache
+{
+    /// Open a cache, loading it from `path` if it is persisted
+    ///
+    /// The cache is only an optimization, so a file that can't be read is
+    /// logged and replaced.
+    pub fn open<P: AsRef<Path>>(path: P, settings: CacheSettings) -> PageCache
+    {
+        let path = if settings.persist { Some(path.as_ref().to_owned()) } else { None };
+
+        let entries = match path
+        {
+            Some(ref path) => state::load_json(path).unwrap_or_else(|e|
+            {
+                if e.kind() != io::ErrorKind::NotFound
+                {
+                    warn!("Could not load the page cache, starting empty: {}", e);
+                }
+                HashMap::new()
+            }),
+            None => HashMap::new(),
+        };
+
+        let mut cache = PageCache
+        {
+            path,
+            settings,
+            entries,
+        };
+        cache.prune();
+
+        cache
+    }
+
+    /// Split friends into those with cached pages and those that need to be looked up
+    ///
+    /// The cached pages are linked to the friends that asked for them. Friends
+    /// whose title is cached as missing are in neither list.
+    pub fn lookup(&self, friends: &[Friend]) -> (Vec<Page>, Vec<Friend>)
+    {
+        let now = now();
+        let mut pages = Vec::new();
+        let mut misses = Vec::new();
+
+        for friend in friends
+        {
+            match self.entries.get(&friend.title)
+            {
+                Some(entry) if entry.expires > now =>
+                {
+                    if let Some(ref page) = entry.page
+                    {
+                        let mut page = page.clone();
+                        page.friends = vec![friend.id];
+                        pages.push(page);
+                    }
+                },
+                _ => misses.push(friend.clone()),
+            }
+        }
+
+        (pages, misses)
+    }
+
+    /// Remember what was found for some friends that were looked up
+    pub fn store(&mut self, friends: &[Friend], pages: &[Page])
+    {
+        if self.settings.ttl == 0
+        {
+            return;
+        }
+
+        let now = now();
+
+        for friend in friends
+        {
+            let page = pages.iter()
+                .find(|page| page.friends.contains(&friend.id))
+                .map(|page| Page
+                {
+                    friends: Vec::new(),
+                    ..page.clone()
+                });
+
+            let ttl = if page.is_some() { self.settings.ttl } else { self.settings.missing_ttl };
+
+            self.entries.insert(friend.title.clone(), Entry
+            {
+                page,
+                suggestions: None,
+                expires: now + ttl,
+            });
+        }
+
+        self.prune();
+
+        if let Err(e) = self.save()
+        {
+            warn!("Could not save the page cache: {}", e);
+        }
+    }
+
+    /// Get the suggestions remembered for a title with no page
+    pub fn suggestions(&self, title: &str) -> Option<&[Suggestion]>
+    {
+        match self.entries.get(title)
+        {
+            Some(entry) if entry.expires > now() && entry.page.is_none() =>
+                entry.suggestions.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Remember the suggestions found for titles with no page
+    ///
+    /// They are kept with the title's missing entry, so they run out with it.
+    pub fn store_suggestions(&mut self, suggestions: &HashMap<String, Vec<Suggestion>>)
+    {
+        let mut changed = false;
+
+        for (title, found) in suggestions
+        {
+            if let Some(entry) = self.entries.get_mut(title)
+            {
+                if entry.page.is_none()
+                {
+                    entry.suggestions = Some(found.clone());
+                    changed = true;
+                }
+            }
+        }
+
+        if changed
+        {
+            if let Err(e) = self.save()
+            {
+                warn!("Could not save the page cache: {}", e);
+            }
+        }
+    }
+
+    fn prune(&mut self)
+    {
+        let now = now();
+        self.entries.retain(|_, entry| entry.expires > now);
+    }
+
+    fn save(&self) -> io::Result<()>
+    {
+        match self.path
+        {
+            Some(ref path) => state::save_json(path, &self.entries),
+            None => Ok(()),
+        }
+    }
+}
+
+// ==============================
+// Serde structs below
+// ==============================
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry
+{
+    /// The page that was found, or `None` if there wasn't one
+    page: Option<Page>,
+    /// Pages that might be what was meant, if there was no page and the wiki
+    /// was searched
+    #[serde(default)]
+    suggestions: Option<Vec<Suggestion>>,
+    /// When the entry runs out, in seconds since the unix epoch
+    expires: u64,
+}
@@ -0,0 +1,343 @@
+/// The longest an excerpt can be, in characters
+pub static MAX_EXCERPT: usize = 300;
+
+// paragraphs shorter than this are probably captions or leftovers, not prose
+static MIN_PARAGRAPH: usize = 40;
+
+/// Find the first paragraph of prose in a page's wikitext
+///
+/// Templates (including infoboxes), tables, references, comments and files are
+/// removed, links are replaced with their text, and the result is cut down to
+/// `MAX_EXCERPT` characters.
+pub fn excerpt(wikitext: &str) -> Option<String>
+{
+    let text = strip(wikitext, "<!--", "-->");
+    let text = strip_refs(&text);
+    let text = strip(&text, "{{", "}}");
+    let text = strip(&text, "{|", "|}");
+
+    let mut paragraph = String::new();
+
+    for line in text.lines().chain(::std::iter::once(""))
+    {
+        let line = line.trim();
+
+        if !line.is_empty() && !is_structure(line)
+        {
+            paragraph.push_str(line);
+            paragraph.push(' ');
+            continue;
+        }
+
+        // a blank line, heading, list or similar ends the paragraph
+        let cleaned = clean(&paragraph);
+        paragraph.clear();
+
+        if cleaned.chars().count() >= MIN_PARAGRAPH
+        {
+            return Some(shorten(cleaned));
+        }
+    }
+
+    None
+}
+
+// lines that aren't part of a paragraph of prose
+fn is_structure(line: &str) -> bool
+{
+    let starts = ["=", "*", "#", ":", ";", "|", "!", "__", "----"];
+
+    starts.iter().any(|s| line.starts_with(s)) ||
+        // a line with just a file or category on it
+        (line.starts_with("[[") && line.ends_with("]]") && is_hidden_link(&line[2..]))
+}
+
+/// Remove everything between `open` and `close`, allowing them to nest
+pub fn strip(text: &str, open: &str, close: &str) -> String
+{
+    let mut fmt = String::with_capacity(text.len());
+    let mut depth = 0;
+    let mut i = 0;
+
+    while i < text.len()
+    {
+        let rest = &text[i..];
+
+        if rest.starts_with(open)
+        {
+            depth += 1;
+            i += open.len();
+        }
+        else if depth > 0 && rest.starts_with(close)
+        {
+            depth -= 1;
+            i += close.len();
+        }
+        else
+        {
+            let c = rest.chars().next().unwrap(); // i is always on a char boundary
+            if depth == 0
+            {
+                fmt.push(c);
+            }
+            i += c.len_utf8();
+        }
+    }
+
+    fmt
+}
+
+/// Remove `<ref>...</ref>` and `<ref ... />`
+pub fn strip_refs(text: &str) -> String
+{
+    let mut fmt = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find("<ref")
+    {
+        fmt.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let end = match rest.find('>')
+        {
+            Some(end) => end,
+            None => return fmt, // unclosed tag, the rest is garbage
+        };
+
+        if rest[..end].ends_with('/')
+        {
+            rest = &rest[end + 1..];
+        }
+        else
+        {
+            match rest.find("</ref>")
+            {
+                Some(close) => rest = &rest[close + "</ref>".len()..],
+                None => return fmt,
+            }
+        }
+    }
+
+    fmt.push_str(rest);
+    fmt
+}
+
+/// Replace links with their text, and remove formatting and tags
+pub fn clean(text: &str) -> String
+{
+    let mut fmt = String::with_capacity(text.len());
+    let mut i = 0;
+
+    while i < text.len()
+    {
+        let rest = &text[i..];
+
+        if rest.starts_with("[[")
+        {
+            match find_close(rest, "[[", "]]")
+            {
+                Some(end) =>
+                {
+                    let inner = &rest[2..end];
+                    if !is_hidden_link(inner)
+                    {
+                        // [[Target|Label]] shows the label, [[Target]] shows the target
+                        let label = match inner.rfind('|')
+                        {
+                            Some(bar) => &inner[bar + 1..],
+                            None => inner,
+                        };
+                        fmt.push_str(&clean(label));
+                    }
+                    i += end + 2;
+                    continue;
+                },
+                None => break,
+            }
+        }
+        else if rest.starts_with("[http") || rest.starts_with("[//")
+        {
+            match rest.find(']')
+            {
+                Some(end) =>
+                {
+                    // [http://example.com Label] shows the label
+                    if let Some(space) = rest[..end].find(' ')
+                    {
+                        fmt.push_str(&rest[space + 1..end]);
+                    }
+                    i += end + 1;
+                    continue;
+                },
+                None => break,
+            }
+        }
+        else if rest.starts_with('<') &&
+            rest[1..].chars().next().map(|c| c.is_alphabetic() || c == '/').unwrap_or(false)
+        {
+            match rest.find('>')
+            {
+                Some(end) =>
+                {
+                    i += end + 1;
+                    continue;
+                },
+                None => break,
+            }
+        }
+
+        let c = rest.chars().next().unwrap();
+        fmt.push(c);
+        i += c.len_utf8();
+    }
+
+    let fmt = fmt
+        .replace("'''", "")
+        .replace("''", "")
+        .replace("&nbsp;", " ");
+
+    fmt.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Find the index of the `close` that matches the `open` at the start of `text`
+pub fn find_close(text: &str, open: &str, close: &str) -> Option<usize>
+{
+    let mut depth = 0;
+    let mut i = 0;
+
+    while i < text.len()
+    {
+        let rest = &text[i..];
+
+        if rest.starts_with(open)
+        {
+            depth += 1;
+            i += open.len();
+        }
+        else if rest.starts_with(close)
+        {
+            depth -= 1;
+            if depth == 0
+            {
+                return Some(i);
+            }
+            i += close.len();
+        }
+        else
+        {
+            i += rest.chars().next().unwrap().len_utf8();
+        }
+    }
+
+    None
+}
+
+// links that don't show up as text
+fn is_hidden_link(inner: &str) -> bool
+{
+    let lc = inner.trim_start_matches(':').to_lowercase();
+
+    ["file:", "image:", "category:"].iter().any(|ns| lc.starts_with(ns))
+}
+
+// cut an excerpt down to size, at the end of a sentence if there's one nearby
+fn shorten(text: String) -> String
+{
+    if text.chars().count() <= MAX_EXCERPT
+    {
+        return text;
+    }
+
+    let cut = text.char_indices()
+        .nth(MAX_EXCERPT)
+        .map(|(i, _)| i)
+        .unwrap_or(text.len());
+    let short = &text[..cut];
+
+    if let Some(end) = short.rfind(". ")
+    {
+        if end > cut / 2
+        {
+            return short[..end + 1].to_owned();
+        }
+    }
+
+    match short.rfind(' ')
+    {
+        Some(space) => format!("{}...", &short[..space]),
+        None => format!("{}...", short),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::{clean, excerpt, find_close, strip, strip_refs, MAX_EXCERPT};
+
+    #[test]
+    fn strip_nested()
+    {
+        assert_eq!(strip("a {{b {{c}} d}} e", "{{", "}}"), "a  e");
+        assert_eq!(strip("a <!-- b --> c", "<!--", "-->"), "a  c");
+        // an unclosed template hides the rest, instead of leaking its insides
+        assert_eq!(strip("a {{b", "{{", "}}"), "a ");
+        assert_eq!(strip("a }} b", "{{", "}}"), "a }} b");
+    }
+
+    #[test]
+    fn find_close_nested()
+    {
+        assert_eq!(find_close("[[a|[[b]]]] c", "[[", "]]"), Some(9));
+        assert_eq!(find_close("{{a}}", "{{", "}}"), Some(3));
+        assert_eq!(find_close("{{a {{b}}", "{{", "}}"), None);
+    }
+
+    #[test]
+    fn refs_removed()
+    {
+        assert_eq!(strip_refs("a<ref>b</ref> c<ref name=\"d\" /> e"), "a c e");
+        assert_eq!(strip_refs("a<ref name=\"b\">c</ref>"), "a");
+        assert_eq!(strip_refs("a<ref>b"), "a");
+    }
+
+    #[test]
+    fn links_cleaned()
+    {
+        assert_eq!(clean("'''Serval''' is a [[Friend]] from [[Japari Park|the park]]."),
+            "Serval is a Friend from the park.");
+        assert_eq!(clean("See [http://example.com the site] or [[File:Serval.png|thumb]]<br/>now"),
+            "See the site or now");
+    }
+
+    #[test]
+    fn excerpt_skips_structure()
+    {
+        let text = "{{Infobox\n|name = Serval\n}}\n\
+            [[File:Serval.png|thumb|A serval]]\n\
+            {| class=\"wikitable\"\n| A table cell that is long enough to be a paragraph\n|}\n\
+            == Heading ==\n\
+            '''Serval''' is a [[Friend]] who lives in the savanna area of [[Japari Park]].<ref>Guide</ref>\n\
+            She is very good at jumping.\n\n\
+            Second paragraph.";
+
+        assert_eq!(excerpt(text).as_deref(), Some("Serval is a Friend who lives in the savanna \
+            area of Japari Park. She is very good at jumping."));
+    }
+
+    #[test]
+    fn short_paragraphs_skipped()
+    {
+        assert_eq!(excerpt("Too short.\n\nAlso short."), None);
+    }
+
+    #[test]
+    fn long_excerpt_shortened()
+    {
+        let sentence = "This sentence is about a friend from the park. ";
+        let text = sentence.repeat(20);
+        let short = excerpt(&text).unwrap();
+
+        assert!(short.chars().count() <= MAX_EXCERPT);
+        assert!(short.ends_with("park."));
+    }
+}
@@ -1,104 +1,138 @@
 use reqwest::blocking::Client;
-use reqwest::header::USER_AGENT;
 
-use super::WikiError;
+use super::{WikiClient, WikiError};
 use super::partial_page::PartialPage;
+use super::query::{query_titles, flag, QueryPage, QueryResult, Rename};
 
 /// An image URL page
 #[derive(Debug)]
 pub struct ImageUrl
 {
     pub title: String,
+    /// Any titles that were normalized to this file's title
+    ///
+    /// Page images and infoboxes give file names as they are written, like
+    /// "File:Serval_anime.png" for "File:Serval anime.png".
+    pub aliases: Vec<String>,
+    /// The URL of the original file
     pub url: String,
+    /// The URL of a thumbnail no wider than the wiki's `thumb_width`
+    ///
+    /// This is `None` if the original is already narrow enough.
+    pub thumb_url: Option<String>,
+    /// The width of the original, in pixels
+    pub width: Option<u32>,
+    /// The height of the original, in pixels
+    pub height: Option<u32>,
+    /// The MIME type of the file, like "image/png"
+    pub mime: Option<String>,
 }
 
 impl ImageUrl
 {
-    pub fn get(client: &Client, partials: &[PartialPage]) -> Result<Vec<ImageUrl>, WikiError>
+    pub fn get(client: &Client, wiki: &WikiClient, partials: &[PartialPage])
+        -> Result<Vec<ImageUrl>, WikiError>
     {
-        let mut images = Vec::with_capacity(partials.len());
-        let mut index = 0;
+        let titles = partials.iter()
+            .filter_map(|partial| partial.image_title.clone())
+            .collect::<Vec<_>>();
+        let width = wiki.thumb_width().map(|w| w.to_string());
 
-        while partials.len() - index > super::MAX_TITLES
+        let mut params = vec![
+                ("prop", "imageinfo"),
+                ("iiprop", "url|size|mime"),
+            ];
+
+        if let Some(ref width) = width
         {
-            images.append(&mut ImageUrl::make_request(
-                client,
-                &partials[index..(index + super::MAX_TITLES)])?);
-            index += super::MAX_TITLES;
+            params.push(("iiurlwidth", width));
         }
 
-        images.append(&mut ImageUrl::make_request(
-                client,
-                &partials[index..])?);
+        let res = query_titles(client, wiki, &params, &titles)?;
 
-        Ok(images)
+        ImageUrl::parse_response(wiki, res)
     }
 
-    fn make_request(client: &Client, partials: &[PartialPage]) -> Result<Vec<ImageUrl>, WikiError>
+    /// Get the URL to link, which is the thumbnail if there is one
+    pub fn link(&self) -> &str
     {
-        assert!(partials.len() <= super::MAX_TITLES);
-
-        use reqwest::{Url, StatusCode};
-
-        let titles = ImageUrl::make_titles(partials);
-
-        let url = Url::parse_with_params("https://japari-library.com/w/api.php",
-            &[
-                ("action", "query"),
-                ("format", "json"),
-                ("prop", "imageinfo"),
-                ("iiprop", "url"),
-                ("titles", &titles)
-            ]).unwrap();
-
-        let res = client.get(url)
-            .header(USER_AGENT, super::user_agent())
-            .send()?;
+        self.thumb_url.as_ref().unwrap_or(&self.url)
+    }
 
-        match res.status()
+    /// Check whether the original is no wider than the wiki's `thumb_width`
+    pub fn fits(&self, wiki: &WikiClient) -> bool
+    {
+        match (self.width, wiki.thumb_width())
         {
-            StatusCode::OK => ImageUrl::parse_response(res.json()?),
-            code => Err(WikiError::StatusError(code)),
+            (Some(width), Some(thumb_width)) => width <= thumb_width,
+            _ => false,
         }
     }
 
-    fn make_titles(partials: &[PartialPage]) -> String
+    /// Check whether the file is an image, going by its MIME type
+    ///
+    /// Files with no MIME type are given the benefit of the doubt.
+    pub fn is_image(&self) -> bool
     {
-        let mut s = String::new();
-        s.push('|'); // this makes sure we have at least one title to query
-                     // otherwise the wiki doesn't even bother with a 'query'
-                     // element, and the we don't handle that.
-        for partial in partials
-        {
-            if let &Some(ref ititle) = &partial.image_title
-            {
-                s.push_str(ititle);
-                s.push('|');
-            }
-        }
-        s
+        self.mime.as_ref()
+            .map(|mime| mime.starts_with("image/"))
+            .unwrap_or(true)
     }
 
-    fn parse_response(mut res: Response) -> Result<Vec<ImageUrl>, WikiError>
+    fn parse_response(wiki: &WikiClient, res: QueryResult<Image>) -> Result<Vec<ImageUrl>, WikiError>
     {
         let mut images = Vec::new();
+        let normalized = res.normalized;
 
-        for (_, image) in res.query.pages.drain()
+        for image in res.pages
         {
-            if image.missing.is_none() & image.invalid.is_none()
+            if !image.missing && !image.invalid
             {
-                let title = image.title
-                    .expect("Valid and non-missing imagedata did not have a 'title'");
-                let imageinfo = image.imageinfo
-                    .expect("Valid and non-missing imagedata did not have an 'imageinfo'");
+                let title = image.title.ok_or(WikiError::Missing("title"))?;
+
+                // a file page with no file, like a redirect, has no imageinfo
+                let imageinfo = match image.imageinfo
+                {
+                    Some(imageinfo) => imageinfo,
+                    None =>
+                    {
+                        warn!("No image info for \"{}\"", title);
+                        continue;
+                    },
+                };
 
-                if imageinfo.len() != 0
+                if let Some(info) = imageinfo.into_iter().next()
                 {
-                    images.push(ImageUrl
+                    let mut image = ImageUrl
+                    {
+                        aliases: get_aliases(&title, &normalized),
+                        title,
+                        url: info.url,
+                        thumb_url: info.thumburl,
+                        width: info.width,
+                        height: info.height,
+                        mime: info.mime,
+                    };
+
+                    // a thumbnail of a small image is just a copy of it
+                    if image.fits(wiki)
                     {
-                        title: title,
-                        url: imageinfo[0].url.clone(),
-                    });
+                        if let (Some(width), Some(height)) = (image.width, image.height)
+                        {
+                            info!("Linking the original of \"{}\", since it is only {}x{}",
+                                image.title, width, height);
+                        }
+                        image.thumb_url = None;
+                    }
+
+                    if wiki.only_images() && !image.is_image()
+                    {
+                        info!("Leaving out \"{}\", since it is not an image ({:?})",
+                            image.title, image.mime);
+                        continue;
+                    }
+
+                    images.push(image);
                 }
             }
         }
@@ -107,35 +141,75 @@ impl ImageUrl
     }
 }
 
+// the titles that were normalized to `title`
+fn get_aliases(title: &str, normalized: &[Rename]) -> Vec<String>
+{
+    normalized.iter()
+        .filter(|rename| rename.to == title)
+        .map(|rename| rename.from.clone())
+        .collect()
+}
+
 // ==============================
 // Serde structs below
 // ==============================
 
 #[derive(Deserialize)]
-struct Response
+struct Image
 {
-    query: Query,
+    title: Option<String>,
+    imageinfo: Option<Vec<ImageInfo>>,
+    #[serde(default, deserialize_with = "flag")]
+    missing: bool,
+    #[serde(default, deserialize_with = "flag")]
+    invalid: bool,
 }
 
-use std::collections::HashMap;
-
-#[derive(Deserialize)]
-struct Query
+impl QueryPage for Image
 {
-    pages: HashMap<String, Image>
-}
+    fn title(&self) -> Option<&str>
+    {
+        self.title.as_deref()
+    }
 
-#[derive(Deserialize)]
-struct Image
-{
-    title: Option<String>,
-    imageinfo: Option<Vec<ImageInfo>>,
-    missing: Option<String>,
-    invalid: Option<String>,
+    fn merge(&mut self, more: Image)
+    {
+        self.imageinfo = self.imageinfo.take().or(more.imageinfo);
+    }
 }
 
 #[derive(Deserialize)]
 struct ImageInfo
 {
     url: String,
+    thumburl: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+    mime: Option<String>,
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::get_aliases;
+    use super::super::query::Rename;
+
+    fn rename(from: &str, to: &str) -> Rename
+    {
+        Rename { from: from.to_owned(), to: to.to_owned() }
+    }
+
+    #[test]
+    fn underscored_names_are_aliases()
+    {
+        let normalized = vec![
+                rename("File:Serval_anime.png", "File:Serval anime.png"),
+                rename("File:serval anime.png", "File:Serval anime.png"),
+                rename("File:Fox_anime.png", "File:Fox anime.png"),
+            ];
+
+        assert_eq!(get_aliases("File:Serval anime.png", &normalized),
+            vec!["File:Serval_anime.png", "File:serval anime.png"]);
+        assert!(get_aliases("File:Serval.png", &normalized).is_empty());
+    }
 }
@@ -0,0 +1,317 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+use reqwest::blocking::Client;
+
+use friend::Friend;
+use state;
+use super::{WikiClient, WikiError};
+use super::query::{query_continued, Rename};
+
+/// Every article title on the wiki, downloaded ahead of time
+///
+/// With an index, titles can be checked and corrected without asking the
+/// wiki. It is built with the `index` command, and goes out of date as the
+/// wiki changes, so a title that isn't in it might still exist.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TitleIndex
+{
+    /// When the index was built, in seconds since the unix epoch
+    pub built: u64,
+    /// The titles of every article that isn't a redirect
+    titles: Vec<String>,
+    /// Every redirect, from its title to the title it leads to
+    redirects: HashMap<String, String>,
+    /// Every title and redirect, by its key
+    #[serde(skip)]
+    keys: HashMap<String, String>,
+}
+
+impl TitleIndex
+{
+    /// Download every article title and redirect from the wiki
+    pub fn build(client: &Client, wiki: &WikiClient) -> Result<TitleIndex, WikiError>
+    {
+        let mut titles = Vec::new();
+        query_continued(client, wiki, &[
+                ("list", "allpages"),
+                ("apnamespace", "0"),
+                ("apfilterredir", "nonredirects"),
+                ("aplimit", "max"),
+            ],
+            |res: AllPagesResponse| titles.extend(res.query.allpages.into_iter().map(|p| p.title)))?;
+
+        let mut redirects = HashMap::new();
+        query_continued(client, wiki, &[
+                ("generator", "allpages"),
+                ("gapnamespace", "0"),
+                ("gapfilterredir", "redirects"),
+                ("gaplimit", "max"),
+                ("redirects", "1"),
+            ],
+            |res: RedirectsResponse| if let Some(query) = res.query
+            {
+                redirects.extend(query.redirects.into_iter().map(|r| (r.from, r.to)));
+            })?;
+
+        Ok(TitleIndex::with_keys(TitleIndex
+        {
+            built: state::now(),
+            titles,
+            redirects,
+            keys: HashMap::new(),
+        }))
+    }
+
+    /// Load an index saved by `save`
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<TitleIndex>
+    {
+        Ok(TitleIndex::with_keys(state::load_json(path)?))
+    }
+
+    /// Save the index, replacing any older one
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()>
+    {
+        state::save_json(path, self)
+    }
+
+    /// The number of articles and redirects in the index
+    pub fn size(&self) -> (usize, usize)
+    {
+        (self.titles.len(), self.redirects.len())
+    }
+
+    /// Find the title the wiki uses for a friend, if it isn't the one they have
+    ///
+    /// Differences in case and spacing are corrected, and a media suffix that
+    /// doesn't exist is matched to a sub-page that starts the same way, like
+    /// "Nexon Game" to "Nexon".
+    pub fn correct(&self, friend: &Friend) -> Option<String>
+    {
+        if let Some(title) = self.find(&friend.title)
+        {
+            return if title == friend.title { None } else { Some(title.to_owned()) };
+        }
+
+        let suffix = match friend.media
+        {
+            Some(ref media) if media.suffix.len() > 1 => &media.suffix,
+            _ => return None,
+        };
+
+        let name = self.find(&friend.name)?;
+        let prefix = key(&format!("{}/", name));
+        let media = key(suffix.trim_start_matches('/'));
+
+        self.titles.iter()
+            .filter(|title| key(title).starts_with(&prefix))
+            .find(|title|
+            {
+                let other = key(title.split_once('/').map(|(_, sub)| sub).unwrap_or(""));
+                other.starts_with(&media) || media.starts_with(&other)
+            })
+            .cloned()
+    }
+
+    /// Find titles that are spelled like one that doesn't exist, closest first
+    pub fn similar(&self, title: &str, max: usize) -> Vec<&str>
+    {
+        let wanted = key(title);
+        let wanted_name = wanted.split('/').next().unwrap_or("");
+        // a typo of one or two letters, or more in a long title
+        let limit = (wanted.chars().count() / 4).max(2);
+
+        let mut found = self.titles.iter()
+            .filter_map(|other|
+            {
+                let other_key = key(other);
+                let other_name = other_key.split('/').next().unwrap_or("");
+
+                // a different sub-page of the right friend is a good suggestion too
+                let d = distance(&wanted, &other_key)
+                    .min(distance(wanted_name, other_name) + 1);
+
+                if d <= limit && other_key != wanted { Some((d, other.as_str())) } else { None }
+            })
+            .collect::<Vec<_>>();
+
+        found.sort();
+        found.into_iter()
+            .map(|(_, title)| title)
+            .take(max)
+            .collect()
+    }
+
+    // the title or redirect with the same key as `title`
+    fn find(&self, title: &str) -> Option<&str>
+    {
+        self.keys.get(&key(title)).map(|t| t.as_str())
+    }
+
+    fn with_keys(mut index: TitleIndex) -> TitleIndex
+    {
+        index.keys = index.titles.iter()
+            .chain(index.redirects.keys())
+            .map(|title| (key(title), title.clone()))
+            .collect();
+
+        index
+    }
+}
+
+// titles that only differ in case and spacing have the same key
+fn key(title: &str) -> String
+{
+    title.replace('_', " ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+// the number of single character changes between two strings
+fn distance(a: &str, b: &str) -> usize
+{
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..b.len() + 1).collect::<Vec<_>>();
+
+    for (i, ca) in a.chars().enumerate()
+    {
+        let mut prev = row[0];
+        row[0] = i + 1;
+
+        for j in 0..b.len()
+        {
+            let cost = if ca == b[j] { 0 } else { 1 };
+            let next = (prev + cost).min(row[j] + 1).min(row[j + 1] + 1);
+            prev = row[j + 1];
+            row[j + 1] = next;
+        }
+    }
+
+    row[b.len()]
+}
+
+// ==============================
+// Serde structs below
+// ==============================
+
+#[derive(Deserialize)]
+struct AllPagesResponse
+{
+    query: AllPagesQuery,
+}
+
+#[derive(Deserialize)]
+struct AllPagesQuery
+{
+    allpages: Vec<AllPage>,
+}
+
+#[derive(Deserialize)]
+struct AllPage
+{
+    title: String,
+}
+
+#[derive(Deserialize)]
+struct RedirectsResponse
+{
+    query: Option<RedirectsQuery>,
+}
+
+#[derive(Deserialize)]
+struct RedirectsQuery
+{
+    #[serde(default)]
+    redirects: Vec<Rename>,
+}
+
+#[cfg(test)]
+mod tests
+{
+    use uuid::Uuid;
+
+    use friend::{Friend, Media};
+    use super::{distance, key, TitleIndex};
+
+    fn index(titles: &[&str], redirects: &[(&str, &str)]) -> TitleIndex
+    {
+        TitleIndex::with_keys(TitleIndex
+        {
+            built: 0,
+            titles: titles.iter().map(|t| t.to_string()).collect(),
+            redirects: redirects.iter().map(|&(f, t)| (f.to_owned(), t.to_owned())).collect(),
+            keys: Default::default(),
+        })
+    }
+
+    fn media(name: &str, suffix: &str) -> Media
+    {
+        Media
+        {
+            name: name.to_owned(),
+            aliases: Vec::new(),
+            suffix: suffix.to_owned(),
+            label: name.to_owned(),
+        }
+    }
+
+    #[test]
+    fn distances()
+    {
+        assert_eq!(distance("serval", "serval"), 0);
+        assert_eq!(distance("serval", "servel"), 1);
+        assert_eq!(distance("serval", "sevral"), 2);
+        assert_eq!(distance("", "fox"), 3);
+        assert_eq!(distance("fox", ""), 3);
+    }
+
+    #[test]
+    fn keys_ignore_case_and_spacing()
+    {
+        assert_eq!(key("Ezo_Red  Fox"), key("ezo red fox"));
+    }
+
+    #[test]
+    fn correct_case_and_spacing()
+    {
+        let index = index(&["Ezo Red Fox", "Serval"], &[]);
+
+        let fox = Friend::new(&"ezo_red fox", Uuid::nil(), &[]);
+        assert_eq!(index.correct(&fox), Some("Ezo Red Fox".to_owned()));
+
+        let serval = Friend::new(&"serval", Uuid::nil(), &[]);
+        assert_eq!(index.correct(&serval), None);
+    }
+
+    #[test]
+    fn correct_media_suffix()
+    {
+        let medias = [media("Nexon Game", "/Nexon Game"), media("Anime", "/Anime")];
+        let index = index(&["Serval", "Serval/Nexon", "Serval/Anime"], &[]);
+
+        let nexon = Friend::new(&"serval/nexon game", Uuid::nil(), &medias);
+        assert_eq!(nexon.title, "Serval/Nexon Game");
+        assert_eq!(index.correct(&nexon), Some("Serval/Nexon".to_owned()));
+
+        let anime = Friend::new(&"serval/anime", Uuid::nil(), &medias);
+        assert_eq!(index.correct(&anime), None);
+
+        // there's nothing to match a missing friend's sub-page to
+        let fox = Friend::new(&"fox/anime", Uuid::nil(), &medias);
+        assert_eq!(index.correct(&fox), None);
+    }
+
+    #[test]
+    fn similar_closest_first()
+    {
+        let index = index(&["Serval", "Servel", "Sevral", "Serval/Anime", "Fossa"], &[]);
+
+        // "Sevral" is three changes away, too far for a title this short
+        assert_eq!(index.similar("Servall", 5), vec!["Serval", "Serval/Anime", "Servel"]);
+        assert_eq!(index.similar("Servall", 1), vec!["Serval"]);
+        assert!(index.similar("Giraffe", 5).is_empty());
+    }
+}
@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+
+use super::excerpt::{clean, find_close, strip, strip_refs};
+
+/// Facts about a friend, from the infobox on their page
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FriendInfo
+{
+    /// The scientific name of the friend's animal
+    pub scientific_name: Option<String>,
+    /// The conservation status of the friend's animal
+    pub conservation_status: Option<String>,
+    /// The media the friend first appeared in
+    pub first_appearance: Option<String>,
+    /// The friend's voice actor
+    pub voice_actor: Option<String>,
+    /// The file title of the infobox's image, like "File:Serval.png"
+    pub image: Option<String>,
+    /// Every parameter in the infobox, cleaned to plain text
+    ///
+    /// The keys are in lowercase, with spaces and underscores removed.
+    pub fields: HashMap<String, String>,
+}
+
+impl FriendInfo
+{
+    /// Read the first infobox template in a page's wikitext
+    ///
+    /// Any template with a name starting with "Infobox" counts. The page title
+    /// is needed to fill in `{{PAGENAME}}`, which is common in image names.
+    pub fn parse(wikitext: &str, title: &str) -> Option<FriendInfo>
+    {
+        let body = FriendInfo::find_infobox(wikitext)?;
+
+        let mut fields = HashMap::new();
+        let mut image = None;
+        for param in split_params(body).into_iter().skip(1) // the first is the name
+        {
+            let eq = match param.find('=')
+            {
+                Some(eq) => eq,
+                None => continue, // positional parameters don't mean anything here
+            };
+
+            let key = normalize(&param[..eq]);
+            let raw = param[eq + 1..].replace("{{PAGENAME}}", title);
+            let raw = strip_refs(&strip(&raw, "<!--", "-->"));
+
+            if key == "image" && image.is_none()
+            {
+                image = image_title(&raw);
+            }
+
+            let value = clean(&strip(&raw, "{{", "}}"));
+
+            if !key.is_empty() && !value.is_empty()
+            {
+                fields.insert(key, value);
+            }
+        }
+
+        Some(FriendInfo
+        {
+            scientific_name: find(&fields, &["scientificname", "binomial", "species"]),
+            conservation_status: find(&fields, &["conservationstatus", "conservation", "status"]),
+            first_appearance: find(&fields, &["firstappearance", "debut", "appearance"]),
+            voice_actor: find(&fields, &["voiceactor", "voiceactress", "seiyuu", "va"]),
+            image,
+            fields,
+        })
+    }
+
+    /// Get a fact by name
+    ///
+    /// The names `scientific_name`, `conservation_status`, `first_appearance` and
+    /// `voice_actor` get the typed fields, anything else is looked up as an
+    /// infobox parameter.
+    pub fn get(&self, name: &str) -> Option<&str>
+    {
+        let typed = match name
+        {
+            "scientific_name" => &self.scientific_name,
+            "conservation_status" => &self.conservation_status,
+            "first_appearance" => &self.first_appearance,
+            "voice_actor" => &self.voice_actor,
+            _ => return self.fields.get(&normalize(name)).map(|v| v.as_str()),
+        };
+
+        typed.as_ref().map(|v| v.as_str())
+    }
+
+    // the inside of the infobox template, without the braces
+    fn find_infobox(wikitext: &str) -> Option<&str>
+    {
+        let mut from = 0;
+
+        while let Some(start) = wikitext[from..].find("{{")
+        {
+            let start = from + start;
+            let rest = &wikitext[start..];
+
+            if rest[2..].trim_start().to_lowercase().starts_with("infobox")
+            {
+                let end = find_close(rest, "{{", "}}")?;
+                return Some(&rest[2..end]);
+            }
+
+            from = start + 2;
+        }
+
+        None
+    }
+}
+
+// split template parameters on the `|`s that aren't inside links or other templates
+fn split_params(body: &str) -> Vec<&str>
+{
+    let mut params = Vec::new();
+    let mut depth = 0i32;
+    let mut start = 0;
+    let mut i = 0;
+
+    while i < body.len()
+    {
+        let rest = &body[i..];
+
+        if rest.starts_with("{{") || rest.starts_with("[[")
+        {
+            depth += 1;
+            i += 2;
+        }
+        else if rest.starts_with("}}") || rest.starts_with("]]")
+        {
+            depth -= 1;
+            i += 2;
+        }
+        else
+        {
+            if depth == 0 && rest.starts_with('|')
+            {
+                params.push(&body[start..i]);
+                start = i + 1;
+            }
+            i += rest.chars().next().unwrap().len_utf8();
+        }
+    }
+
+    params.push(&body[start..]);
+    params
+}
+
+// the file title in an image parameter, which can be a bare file name,
+// or a whole [[File:...]] link
+fn image_title(raw: &str) -> Option<String>
+{
+    let mut name = raw.trim();
+
+    if name.starts_with("[[")
+    {
+        name = name[2..].split(['|', ']']).next().unwrap_or("");
+    }
+
+    for prefix in &["File:", "Image:", "file:", "image:"]
+    {
+        if name.starts_with(prefix)
+        {
+            name = &name[prefix.len()..];
+        }
+    }
+
+    let name = name.trim();
+
+    // anything with a template left in it can't be worked out here
+    if name.is_empty() || name.contains("{{") || name.contains('|')
+    {
+        None
+    }
+    else
+    {
+        Some(format!("File:{}", name))
+    }
+}
+
+fn normalize(key: &str) -> String
+{
+    key.chars()
+        .filter(|c| !c.is_whitespace() && *c != '_')
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+fn find(fields: &HashMap<String, String>, keys: &[&str]) -> Option<String>
+{
+    keys.iter()
+        .filter_map(|k| fields.get(*k))
+        .next()
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::{image_title, split_params, FriendInfo};
+
+    #[test]
+    fn params_split_outside_links_and_templates()
+    {
+        assert_eq!(split_params("Infobox| a = [[B|C]] | d = {{e|f}}|g"),
+            vec!["Infobox", " a = [[B|C]] ", " d = {{e|f}}", "g"]);
+        assert_eq!(split_params("a = [[B|[[C|D]]]]|e"), vec!["a = [[B|[[C|D]]]]", "e"]);
+    }
+
+    #[test]
+    fn nested_links_in_params()
+    {
+        let text = "{{Infobox Friend\n\
+            |image = [[File:Serval.png|thumb|A [[Serval]] in the anime]]\n\
+            |first appearance = [[Kemono Friends (Anime)|Anime]] {{Small|[[Episode 1|ep. 1]]}}\n\
+            |voice_actor = [[Ozaki Yuka]]\n}}";
+        let info = FriendInfo::parse(text, "Serval").unwrap();
+
+        assert_eq!(info.image.as_deref(), Some("File:Serval.png"));
+        assert_eq!(info.first_appearance.as_deref(), Some("Anime"));
+        assert_eq!(info.voice_actor.as_deref(), Some("Ozaki Yuka"));
+    }
+
+    #[test]
+    fn refs_and_comments_left_out_of_params()
+    {
+        let text = "{{Infobox\n\
+            |first appearance = [[Kemono Friends (Anime)|Anime]] <ref>x</ref>\n\
+            |species = Leptailurus serval<ref name=\"iucn\" /><!-- check this -->\n}}";
+        let info = FriendInfo::parse(text, "Serval").unwrap();
+
+        assert_eq!(info.first_appearance.as_deref(), Some("Anime"));
+        assert_eq!(info.scientific_name.as_deref(), Some("Leptailurus serval"));
+    }
+
+    #[test]
+    fn pagename_filled_in()
+    {
+        let text = "{{infobox\n|image = {{PAGENAME}}Anime.png\n|name = {{PAGENAME}}\n}}";
+        let info = FriendInfo::parse(text, "Serval").unwrap();
+
+        assert_eq!(info.image.as_deref(), Some("File:ServalAnime.png"));
+        assert_eq!(info.get("name"), Some("Serval"));
+    }
+
+    #[test]
+    fn no_infobox()
+    {
+        assert!(FriendInfo::parse("{{Quote|Hi}} Just prose.", "Serval").is_none());
+    }
+
+    #[test]
+    fn image_title_forms()
+    {
+        assert_eq!(image_title(" Serval.png "), Some("File:Serval.png".to_owned()));
+        assert_eq!(image_title("File:Serval.png"), Some("File:Serval.png".to_owned()));
+        assert_eq!(image_title("[[Image:Serval.png|200px|thumb]]"),
+            Some("File:Serval.png".to_owned()));
+        assert_eq!(image_title("{{Gallery|Serval.png}}"), None);
+        assert_eq!(image_title(""), None);
+    }
+}
@@ -1,22 +1,34 @@
 pub mod partial_page;
 pub mod image_url;
+pub mod wikitext;
+pub mod query;
+pub mod excerpt;
+pub mod infobox;
+pub mod subpage;
+pub mod suggestion;
+pub mod wiki_client;
+pub mod cache;
+pub mod index;
 
 use uuid::Uuid;
 
 use friend::Friend;
 
+pub use self::wiki_client::WikiClient;
+
 /// A wiki page
 ///
 /// A Page is usually for a friend, but actually describes many pages on the wiki.  
-/// The page is built in two requests, one to get the page title and URL. And
-/// another to get the image URL.
-#[derive(Debug, Clone)]
+/// The page is built in three requests, one to get the page title and URL, one
+/// to get the page's wikitext, and one to get the image URL.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Page
 {
     /// The friends that this page is linked to
     ///
     /// Multiple friends might parse differently, but link
     /// to the same page. So we keep track of all of them.
+    #[serde(skip)]
     pub friends: Vec<Uuid>,
     /// The title of the page
     pub title: String,
@@ -24,6 +36,14 @@ pub struct Page
     pub url: String,
     /// The URL of the image, if any
     pub image: Option<String>,
+    /// How the image was chosen
+    pub image_strategy: Option<ImageStrategy>,
+    /// If this is a disambiguation page, the sub-pages it could mean
+    pub subpages: Option<Vec<subpage::Subpage>>,
+    /// The first paragraph of the page, if one could be found
+    pub excerpt: Option<String>,
+    /// Facts from the page's infobox, if it has one
+    pub info: Option<infobox::FriendInfo>,
 }
 
 impl Page
@@ -46,24 +66,45 @@ impl Page
                     title: partial.title,
                     url: partial.url,
                     image: image_url,
+                    image_strategy: partial.image_strategy,
+                    subpages: partial.subpages,
+                    excerpt: partial.excerpt,
+                    info: partial.info,
                 }
             })
             .collect::<Vec<_>>()
     }
 
+    /// Combine pages with the same title, keeping all of their friends
+    pub fn merge(pages: Vec<Page>) -> Vec<Page>
+    {
+        let mut merged: Vec<Page> = Vec::with_capacity(pages.len());
+
+        for page in pages
+        {
+            match merged.iter_mut().find(|p| p.title == page.title)
+            {
+                Some(existing) => existing.friends.extend(page.friends),
+                None => merged.push(page),
+            }
+        }
+
+        merged
+    }
+
     fn find_image(title: &Option<String>, images: &[image_url::ImageUrl]) -> Option<String>
     {
         let title = match title
         {
-            &Some(ref title) => title,
-            &None => return None,
+            Some(title) => title,
+            None => return None,
         };
 
         for image in images
         {
-            if &image.title == title
+            if &image.title == title || image.aliases.contains(title)
             {
-                return Some(image.url.clone())
+                return Some(image.link().to_owned())
             }
         }
 
@@ -76,11 +117,7 @@ impl Page
 
         for friend in friends
         {
-            if friend.title == title
-            {
-                ids.push(friend.id);
-            }
-            else if aliases.contains(&friend.title)
+            if friend.title == title || aliases.contains(&friend.title)
             {
                 ids.push(friend.id);
             }
@@ -90,11 +127,18 @@ impl Page
     }
 }
 
-/// The user-agent to use for the wiki
-pub fn user_agent() -> String
+/// How a page's main image was chosen
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ImageStrategy
 {
-    format!("{}/{}", ::secrets::user(), ::VERSION)
+    /// The wiki's own choice, from the PageImages extension
+    PageImage,
+    /// The image in the page's infobox
+    Infobox,
+    /// A guess from the names of the files on the page
+    Heuristic,
 }
+
 /// The maximum number of titles that can be put into one wiki request
 ///
 /// The actual number is 50, but due to wiki weirdness and the high
@@ -109,6 +153,22 @@ pub enum WikiError
 {
     StatusError(StatusCode),
     RequestError(Error),
+    /// The response was not the JSON that was expected
+    Parse(::serde_json::Error),
+    /// The wiki refused the request, with a MediaWiki error code and message
+    ApiError
+    {
+        code: String,
+        info: String,
+    },
+    /// The wiki answered, but warned that it ignored part of the request
+    ApiWarning
+    {
+        code: String,
+        info: String,
+    },
+    /// The response was missing a field that should always be there
+    Missing(&'static str),
 }
 
 impl From<Error> for WikiError
@@ -118,3 +178,11 @@ impl From<Error> for WikiError
         WikiError::RequestError(err)
     }
 }
+
+impl From<::serde_json::Error> for WikiError
+{
+    fn from(err: ::serde_json::Error) -> WikiError
+    {
+        WikiError::Parse(err)
+    }
+}
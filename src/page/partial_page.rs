@@ -1,7 +1,11 @@
 use reqwest::blocking::Client;
 
 use friend::Friend;
-use super::WikiError;
+use super::{ImageStrategy, WikiClient, WikiError};
+use super::excerpt::excerpt;
+use super::infobox::FriendInfo;
+use super::query::{query_titles, flag, QueryPage, QueryResult, Rename};
+use super::subpage::Subpage;
 
 /// A partially made page
 #[derive(Debug)]
@@ -9,140 +13,225 @@ pub struct PartialPage
 {
     /// The title of the page
     pub title: String,
-    /// Any titles that were normalized to this page's title
+    /// Any titles that were normalized or redirected to this page's title
     pub aliases: Vec<String>,
     /// The URL of the page
     pub url: String,
     /// The image URL, if any
+    ///
+    /// This is `None` until `select_image` is called.
     pub image_title: Option<String>,
+    /// How the image was chosen
+    pub image_strategy: Option<ImageStrategy>,
+    /// If this is a disambiguation page, the sub-pages it could mean
+    ///
+    /// Disambiguation pages never have an image, since it would be a guess.
+    pub subpages: Option<Vec<Subpage>>,
+    /// The first paragraph of the page, once the wikitext is read
+    pub excerpt: Option<String>,
+    /// Facts from the page's infobox, once the wikitext is read
+    pub info: Option<FriendInfo>,
+    /// The page's main image according to the wiki, if it has the PageImages extension
+    page_image: Option<String>,
+    /// Every file used on the page
+    images: Vec<String>,
 }
 
 impl PartialPage
 {
-    pub fn get(client: &Client, friends: &[Friend]) -> Result<Vec<PartialPage>, WikiError>
+    pub fn get(client: &Client, wiki: &WikiClient, friends: &[Friend])
+        -> Result<Vec<PartialPage>, WikiError>
     {
-        let mut partials = Vec::with_capacity(friends.len());
-        let mut index = 0;
+        let titles = friends.iter()
+            .map(|friend| friend.title.clone())
+            .collect::<Vec<_>>();
 
-        while friends.len() - index > super::MAX_TITLES
+        // only look at categories if there's one that marks disambiguation pages
+        let category = wiki.disambiguation_category();
+        let prop = if category.is_some()
         {
-            partials.append(&mut PartialPage::make_request(
-                client,
-                &friends[index..(index + super::MAX_TITLES)])?);
-            index += super::MAX_TITLES;
+            "images|info|pageprops|pageimages|categories"
         }
+        else
+        {
+            "images|info|pageprops|pageimages"
+        };
 
-        partials.append(&mut PartialPage::make_request(
-                client,
-                &friends[index..])?);
-
-        Ok(partials)
-    }
-
-    fn make_request(client: &Client, friends: &[Friend]) -> Result<Vec<PartialPage>, WikiError>
-    {
-        assert!(friends.len() <= super::MAX_TITLES);
-
-        use reqwest::{Url, StatusCode};
-        use reqwest::header::USER_AGENT;
-
-        let titles = PartialPage::make_titles(&friends);
-
-        let url = Url::parse_with_params("https://www.japari-library.com/w/api.php",
-            &[
-                ("action", "query"),
-                ("format", "json"),
-                ("prop", "images|info"),
+        let mut params = vec![
+                ("prop", prop),
                 ("inprop", "url"),
-                ("imlimit", "500"),
-                ("titles", &titles),
-            ]).unwrap();
-
-        let res = client.get(url)
-            .header(USER_AGENT, super::user_agent())
-            .send()?;
+                ("piprop", "name"),
+                ("ppprop", "disambiguation"),
+                ("redirects", "1"),
+                ("imlimit", "max"),
+            ];
 
-        match res.status()
+        if let Some(category) = category
         {
-            StatusCode::OK => Ok(PartialPage::parse_response(res.json()?)),
-            code => Err(WikiError::StatusError(code))
+            params.push(("clcategories", category));
+            params.push(("cllimit", "max"));
         }
-    }
 
-    fn make_titles(friends: &[Friend]) -> String
-    {
-        let mut s = String::new();
-        // for some reason, if we ask the wiki for a weird page like "Main Page"
-        // it sometimes gives us a list rather than a map.
-        // I don't know why this is, but we can prevent it by always requesting
-        // at least one regular page, like "Serval"
-        s.push_str("Serval|");
-        for friend in friends
-        {
-            s.push_str(&friend.title);
-            s.push('|');
-        }
-        s
-    }
-
-    fn parse_response(res: Response) -> Vec<PartialPage>
-    {
-        let mut query = res.query;
-
-        let normalized = query.normalized;
+        let res = query_titles(client, wiki, &params, &titles)?;
+        let mut partials = PartialPage::parse_response(wiki, res);
 
-        let mut partials = Vec::new();
-        for (_, page) in query.pages.drain()
+        for partial in partials.iter_mut()
         {
-            if let Some(partial) = PartialPage::parse_page(page, &normalized)
+            if let Some(ref mut subpages) = partial.subpages
             {
-                partials.push(partial);
+                // the page is still a disambiguation page without its list
+                match Subpage::get(client, wiki, &partial.title)
+                {
+                    Ok(found) => *subpages = found,
+                    Err(e) => warn!("Could not list the pages on \"{}\": {:?}", partial.title, e),
+                }
             }
-            // otherwise maybe log it?
         }
-        partials
+
+        Ok(partials)
     }
 
-    fn parse_page(page: PageJSON, normalized: &Option<Vec<Normalized>>) -> Option<PartialPage>
+    fn parse_response(wiki: &WikiClient, res: QueryResult<PageJSON>) -> Vec<PartialPage>
     {
-        if page.invalid.is_some() || page.missing.is_some()
+        let mut renames = res.normalized;
+        renames.extend(res.redirects);
+
+        res.pages.into_iter()
+            .filter_map(|page| PartialPage::parse_page(wiki, page, &renames))
+            .collect()
+    }
+
+    fn parse_page(wiki: &WikiClient, page: PageJSON, renames: &[Rename])
+        -> Option<PartialPage>
+    {
+        if page.invalid || page.missing
         {
+            // these friends get suggestions instead, see `suggestion`
+            info!("No page for \"{}\"", page.title.unwrap_or_default());
             None
         }
         else
         {
+            let disambiguation = PartialPage::is_disambiguation(wiki, &page);
+
             let title = page.title?;
-            let url = page.fullurl?;
+            let url = page.fullurl.unwrap_or_else(|| wiki.page_url(&title));
 
-            let aliases = PartialPage::get_aliases(&title, normalized);
-            let image_title = PartialPage::select_image(&title, &page.images);
+            let aliases = PartialPage::get_aliases(&title, renames);
+
+            let subpages = if disambiguation
+            {
+                info!("\"{}\" is a disambiguation page", title);
+                Some(Vec::new()) // the sub-pages are filled in later
+            }
+            else
+            {
+                None
+            };
 
             Some(PartialPage
             {
                 title,
                 aliases,
                 url,
-                image_title,
+                image_title: None,
+                image_strategy: None,
+                subpages,
+                excerpt: None,
+                info: None,
+                page_image: page.pageimage.map(|name| format!("File:{}", name)),
+                images: page.images
+                    .map(|images| images.into_iter().map(|image| image.title).collect())
+                    .unwrap_or_default(),
             })
         }
     }
 
-    fn get_aliases(title: &str, normalized: &Option<Vec<Normalized>>) -> Vec<String>
+    fn is_disambiguation(wiki: &WikiClient, page: &PageJSON) -> bool
+    {
+        if let Some(ref props) = page.pageprops
+        {
+            if props.disambiguation
+            {
+                return true;
+            }
+        }
+
+        match (wiki.disambiguation_category(), &page.categories)
+        {
+            (Some(category), Some(categories)) => categories.iter()
+                .any(|c| c.title == category),
+            _ => false,
+        }
+    }
+
+    // every title that leads to this one, through any number of normalizations
+    // and redirects. A friend's title might be normalized, then the normalized
+    // title redirected.
+    fn get_aliases(title: &str, renames: &[Rename]) -> Vec<String>
+    {
+        let mut aliases: Vec<String> = Vec::new();
+        let mut targets = vec![title.to_owned()];
+
+        while let Some(target) = targets.pop()
+        {
+            for rename in renames.iter()
+                .filter(|r| r.to == target)
+            {
+                if rename.from != title && !aliases.contains(&rename.from)
+                {
+                    aliases.push(rename.from.clone());
+                    targets.push(rename.from.clone());
+                }
+            }
+        }
+
+        aliases
+    }
+
+    /// Read the page's excerpt and infobox from its wikitext
+    pub fn read_text(&mut self, wikitext: &str)
+    {
+        self.excerpt = excerpt(wikitext);
+        self.info = FriendInfo::parse(wikitext, &self.title);
+    }
+
+    /// Choose the page's main image
+    ///
+    /// The image the wiki says is the main one is used if there is one, then
+    /// the infobox image, and then a guess from the file names on the page.
+    /// For the infobox to be used, `read_text` needs to be called first.
+    pub fn select_image(&mut self)
     {
-        if let Some(ref normalized) = normalized
+        if self.subpages.is_some()
+        {
+            return;
+        }
+
+        let infobox_image = self.info.as_ref()
+            .and_then(|info| info.image.clone());
+
+        let (image, strategy) = if let Some(image) = self.page_image.clone()
+        {
+            (Some(image), ImageStrategy::PageImage)
+        }
+        else if let Some(image) = infobox_image
         {
-            normalized.iter()
-                .filter(|n| n.to == title)
-                .map(|n| n.from.clone())
-                .collect::<Vec<_>>()
+            (Some(image), ImageStrategy::Infobox)
         }
         else
         {
-            Vec::new()
+            (PartialPage::guess_image(&self.title, &self.images), ImageStrategy::Heuristic)
+        };
+
+        if image.is_some()
+        {
+            self.image_title = image;
+            self.image_strategy = Some(strategy);
         }
     }
 
-    fn select_image(title: &str, images: &Option<Vec<Image>>) -> Option<String>
+    fn guess_image(title: &str, images: &[String]) -> Option<String>
     {
         //take just the name as the title, no media
         let title =
@@ -167,42 +256,37 @@ impl PartialPage
             "gif", "bmp", "tiff"
         ];
 
-        if let Some(ref images) = images
-        {
-            let mut selected = None;
+        let mut selected = None;
 
-            for image in images.iter()
-                .map(|image| image.title.clone()) // just the titles
-                .filter(|image| exts.iter() // only images
-                        .any(|ext| image.to_lowercase().ends_with(ext)))
+        for image in images.iter()
+            .filter(|image| exts.iter() // only images
+                    .any(|ext| image.to_lowercase().ends_with(ext)))
+            .cloned()
+        {
+            // initially set the selected image to the first one
+            if selected.is_none()
             {
-                // initially set the selected image to the first one
-                if selected.is_none()
-                {
-                    selected = Some(image.clone());
-                }
-
-                let lc = image.to_lowercase();
-                // if the title contains "original", select it and
-                // don't continue
-                if lc.contains("original")
-                {
-                    selected = Some(image);
-                    break;
-                }
-                // if the title contains the page title, select it,
-                // but continue in case another one later on is a
-                // better match
-                if lc.contains(&title.to_lowercase())
-                {
-                    selected = Some(image);
-                }
+                selected = Some(image.clone());
             }
 
-            return selected;
+            let lc = image.to_lowercase();
+            // if the title contains "original", select it and
+            // don't continue
+            if lc.contains("original")
+            {
+                selected = Some(image);
+                break;
+            }
+            // if the title contains the page title, select it,
+            // but continue in case another one later on is a
+            // better match
+            if lc.contains(&title.to_lowercase())
+            {
+                selected = Some(image);
+            }
         }
 
-        None
+        selected
     }
 }
 
@@ -211,35 +295,56 @@ impl PartialPage
 // ==============================
 
 #[derive(Deserialize)]
-struct Response
+struct PageJSON
 {
-    query: Query,
+    title: Option<String>,
+    images: Option<Vec<Image>>,
+    fullurl: Option<String>,
+    pageprops: Option<PageProps>,
+    pageimage: Option<String>,
+    categories: Option<Vec<Category>>,
+    #[serde(default, deserialize_with = "flag")]
+    missing: bool,
+    #[serde(default, deserialize_with = "flag")]
+    invalid: bool,
 }
 
-use std::collections::HashMap;
+impl QueryPage for PageJSON
+{
+    fn title(&self) -> Option<&str>
+    {
+        self.title.as_deref()
+    }
+
+    fn merge(&mut self, more: PageJSON)
+    {
+        merge_list(&mut self.images, more.images);
+        merge_list(&mut self.categories, more.categories);
+        self.fullurl = self.fullurl.take().or(more.fullurl);
+        self.pageprops = self.pageprops.take().or(more.pageprops);
+        self.pageimage = self.pageimage.take().or(more.pageimage);
+    }
+}
 
-#[derive(Deserialize)]
-struct Query
+fn merge_list<T>(list: &mut Option<Vec<T>>, more: Option<Vec<T>>)
 {
-    normalized: Option<Vec<Normalized>>,
-    pages: HashMap<String, PageJSON>,
+    if let Some(more) = more
+    {
+        list.get_or_insert_with(Vec::new).extend(more);
+    }
 }
 
 #[derive(Deserialize)]
-struct Normalized
+struct PageProps
 {
-    from: String,
-    to: String,
+    #[serde(default, deserialize_with = "flag")]
+    disambiguation: bool,
 }
 
 #[derive(Deserialize)]
-struct PageJSON
+struct Category
 {
-    title: Option<String>,
-    images: Option<Vec<Image>>,
-    fullurl: Option<String>,
-    missing: Option<String>,
-    invalid: Option<String>,
+    title: String,
 }
 
 #[derive(Deserialize)]
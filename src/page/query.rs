@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+
+use reqwest::blocking::Client;
+use serde::de::{Deserialize, DeserializeOwned, Deserializer};
+use serde_json::{from_value, Value};
+
+use super::{WikiClient, WikiError};
+
+/// A page in the response to a `query` request
+///
+/// Each module that asks the wiki about pages has its own type for the
+/// properties it wants.
+pub trait QueryPage: DeserializeOwned
+{
+    /// The title of the page, if the wiki gave one
+    fn title(&self) -> Option<&str>;
+
+    /// Add the properties from a continued response to this page
+    ///
+    /// Long lists, like a page's images, can be split over several responses.
+    fn merge(&mut self, more: Self);
+}
+
+/// Everything the wiki said about a set of titles
+pub struct QueryResult<P>
+{
+    /// The pages, in no particular order
+    pub pages: Vec<P>,
+    /// Titles the wiki changed to a standard form, like "serval" to "Serval"
+    pub normalized: Vec<Rename>,
+    /// Titles that are redirects to other pages
+    pub redirects: Vec<Rename>,
+}
+
+/// A normalized or redirected title
+#[derive(Debug, Deserialize)]
+pub struct Rename
+{
+    pub from: String,
+    pub to: String,
+}
+
+/// Ask the wiki about some titles
+///
+/// The titles are split into requests of at most `MAX_TITLES`, and any
+/// `continue` in a response is followed until the wiki has given everything.
+/// `params` are the request-specific parameters, like `prop`; the titles are
+/// added here.
+pub fn query_titles<P: QueryPage>(
+    client: &Client,
+    wiki: &WikiClient,
+    params: &[(&str, &str)],
+    titles: &[String])
+    -> Result<QueryResult<P>, WikiError>
+{
+    let mut result = QueryResult
+    {
+        pages: Vec::new(),
+        normalized: Vec::new(),
+        redirects: Vec::new(),
+    };
+
+    // the same title twice would have its properties merged twice
+    let mut unique: Vec<String> = Vec::with_capacity(titles.len());
+    for title in titles
+    {
+        if !unique.contains(title)
+        {
+            unique.push(title.clone());
+        }
+    }
+
+    for chunk in unique.chunks(super::MAX_TITLES)
+    {
+        let titles = chunk.join("|");
+
+        let mut all = vec![("titles", titles.as_str())];
+        all.extend_from_slice(params);
+
+        query_continued(client, wiki, &all, |res: Response<P>|
+        {
+            if let Some(query) = res.query
+            {
+                result.add(query);
+            }
+        })?;
+    }
+
+    Ok(result)
+}
+
+/// Make a `query` request, following `continue` until the wiki has given everything
+///
+/// Each response is passed to `each` as it comes in. The action and format are
+/// added to `params` here.
+pub fn query_continued<T, F>(
+    client: &Client,
+    wiki: &WikiClient,
+    params: &[(&str, &str)],
+    mut each: F)
+    -> Result<(), WikiError>
+    where T: DeserializeOwned,
+          F: FnMut(T)
+{
+    let mut cont: Vec<(String, String)> = Vec::new();
+
+    loop
+    {
+        let mut all = vec![
+                ("action", "query"),
+                ("format", "json"),
+                ("formatversion", "2"),
+            ];
+        all.extend_from_slice(params);
+        all.extend(cont.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+
+        let mut res: Value = wiki.query(client, &all)?;
+        let next = res.get_mut("continue").map(Value::take);
+
+        each(from_value(res)?);
+
+        cont = match next
+        {
+            Some(Value::Object(next)) => next.into_iter()
+                .map(|(k, v)| match v
+                {
+                    Value::String(s) => (k, s),
+                    v => (k, v.to_string()),
+                })
+                .collect(),
+            _ => return Ok(()),
+        };
+    }
+}
+
+impl<P: QueryPage> QueryResult<P>
+{
+    fn add(&mut self, query: Query<P>)
+    {
+        self.normalized.extend(query.normalized.unwrap_or_default());
+        self.redirects.extend(query.redirects.unwrap_or_default());
+
+        let pages = match query.pages
+        {
+            Some(Pages::List(pages)) => pages,
+            Some(Pages::Map(pages)) => pages.into_values().collect(),
+            None => Vec::new(),
+        };
+
+        for page in pages
+        {
+            // a continued response repeats pages that already have some properties
+            let existing = page.title()
+                .and_then(|title| self.pages.iter()
+                    .position(|p| p.title() == Some(title)));
+
+            match existing
+            {
+                Some(i) => self.pages[i].merge(page),
+                None => self.pages.push(page),
+            }
+        }
+    }
+}
+
+/// Read a flag like `missing`, which is `""` in older responses and `true` in newer ones
+pub fn flag<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error>
+{
+    Ok(match Value::deserialize(deserializer)?
+    {
+        Value::Bool(b) => b,
+        Value::Null => false,
+        _ => true,
+    })
+}
+
+// ==============================
+// Serde structs below
+// ==============================
+
+#[derive(Deserialize)]
+struct Response<P>
+{
+    query: Option<Query<P>>,
+}
+
+#[derive(Deserialize)]
+struct Query<P>
+{
+    normalized: Option<Vec<Rename>>,
+    redirects: Option<Vec<Rename>>,
+    pages: Option<Pages<P>>,
+}
+
+// pages are a map by ID in older responses, and a list in newer ones
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Pages<P>
+{
+    List(Vec<P>),
+    Map(HashMap<String, P>),
+}
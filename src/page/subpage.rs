@@ -0,0 +1,76 @@
+use reqwest::blocking::Client;
+
+use super::{WikiClient, WikiError};
+
+/// The most sub-pages to list for one page
+pub static MAX_SUBPAGES: usize = 20;
+
+/// A sub-page of another page, like "Serval/Anime" is to "Serval"
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Subpage
+{
+    /// The full title of the sub-page
+    pub title: String,
+    /// The URL of the sub-page
+    pub url: String,
+}
+
+impl Subpage
+{
+    /// Get the sub-pages of a page
+    pub fn get(client: &Client, wiki: &WikiClient, title: &str) -> Result<Vec<Subpage>, WikiError>
+    {
+        let prefix = format!("{}/", title);
+        let limit = MAX_SUBPAGES.to_string();
+
+        let res: Response = wiki.query(client, &[
+                ("action", "query"),
+                ("format", "json"),
+                ("list", "allpages"),
+                ("apnamespace", "0"),
+                ("apfilterredir", "nonredirects"),
+                ("aplimit", &limit),
+                ("apprefix", &prefix),
+            ])?;
+
+        Ok(res.query.allpages.into_iter()
+            .map(|page| Subpage
+            {
+                url: wiki.page_url(&page.title),
+                title: page.title,
+            })
+            .collect())
+    }
+
+    /// The part of the title after the parent page, like "Anime" in "Serval/Anime"
+    pub fn label(&self) -> &str
+    {
+        match self.title.find('/')
+        {
+            Some(i) => &self.title[i + 1..],
+            None => &self.title,
+        }
+    }
+}
+
+// ==============================
+// Serde structs below
+// ==============================
+
+#[derive(Deserialize)]
+struct Response
+{
+    query: Query,
+}
+
+#[derive(Deserialize)]
+struct Query
+{
+    allpages: Vec<AllPage>,
+}
+
+#[derive(Deserialize)]
+struct AllPage
+{
+    title: String,
+}
@@ -0,0 +1,127 @@
+use reqwest::blocking::Client;
+
+use super::{WikiClient, WikiError};
+use super::index::TitleIndex;
+
+/// The most suggestions to give for one title
+pub static MAX_SUGGESTIONS: usize = 3;
+
+/// A page that might be the one the user meant
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Suggestion
+{
+    /// The title of the page
+    pub title: String,
+    /// The URL of the page
+    pub url: String,
+}
+
+impl Suggestion
+{
+    /// Find pages with titles close to one that doesn't exist
+    ///
+    /// This tries the wiki's `opensearch` first, which is good at finding
+    /// titles with the right start but the wrong end (like a bad media). If that
+    /// finds nothing, a full text search is tried instead.
+    pub fn get(client: &Client, wiki: &WikiClient, title: &str)
+        -> Result<Vec<Suggestion>, WikiError>
+    {
+        let mut found = Suggestion::open_search(client, wiki, title)?;
+
+        if found.is_empty()
+        {
+            found = Suggestion::search(client, wiki, title)?;
+        }
+
+        found.retain(|s| s.title != title);
+        found.truncate(MAX_SUGGESTIONS);
+
+        Ok(found)
+    }
+
+    /// Find pages with titles spelled like one that doesn't exist, without asking the wiki
+    pub fn from_index(wiki: &WikiClient, index: &TitleIndex, title: &str) -> Vec<Suggestion>
+    {
+        index.similar(title, MAX_SUGGESTIONS).into_iter()
+            .map(|found| Suggestion
+            {
+                title: found.to_owned(),
+                url: wiki.page_url(found),
+            })
+            .collect()
+    }
+
+    fn open_search(client: &Client, wiki: &WikiClient, title: &str)
+        -> Result<Vec<Suggestion>, WikiError>
+    {
+        let limit = (MAX_SUGGESTIONS + 1).to_string();
+
+        let res: OpenSearch = wiki.query(client, &[
+                ("action", "opensearch"),
+                ("format", "json"),
+                ("namespace", "0"),
+                ("limit", &limit),
+                ("search", title),
+            ])?;
+
+        let (_, titles, _, urls) = res;
+
+        Ok(titles.into_iter()
+            .zip(urls)
+            .map(|(title, url)| Suggestion
+            {
+                title,
+                url,
+            })
+            .collect())
+    }
+
+    fn search(client: &Client, wiki: &WikiClient, title: &str)
+        -> Result<Vec<Suggestion>, WikiError>
+    {
+        let limit = (MAX_SUGGESTIONS + 1).to_string();
+
+        let res: Response = wiki.query(client, &[
+                ("action", "query"),
+                ("format", "json"),
+                ("list", "search"),
+                ("srnamespace", "0"),
+                ("srprop", ""),
+                ("srlimit", &limit),
+                ("srsearch", title),
+            ])?;
+
+        Ok(res.query.search.into_iter()
+            .map(|result| Suggestion
+            {
+                url: wiki.page_url(&result.title),
+                title: result.title,
+            })
+            .collect())
+    }
+}
+
+// ==============================
+// Serde structs below
+// ==============================
+
+// the search, the titles, the descriptions, and the URLs
+type OpenSearch = (String, Vec<String>, Vec<String>, Vec<String>);
+
+#[derive(Deserialize)]
+struct Response
+{
+    query: Query,
+}
+
+#[derive(Deserialize)]
+struct Query
+{
+    search: Vec<SearchResult>,
+}
+
+#[derive(Deserialize)]
+struct SearchResult
+{
+    title: String,
+}
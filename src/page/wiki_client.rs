@@ -0,0 +1,155 @@
+use reqwest::blocking::Client;
+use reqwest::header::USER_AGENT;
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde_json::{from_value, Value};
+
+use config::{Config, Wiki};
+use super::WikiError;
+
+/// Warnings that mean some of a request was ignored, so the result can't be trusted
+///
+/// `toomanyvalues` means some titles were dropped, which would look like the
+/// pages don't exist. `truncatedresult` isn't here, because the rest of the
+/// result comes with `continue`, which is followed.
+static SERIOUS_WARNINGS: &[&str] = &["toomanyvalues"];
+
+/// A MediaWiki site
+///
+/// This holds everything needed to talk to one wiki, so that the same code
+/// can serve any MediaWiki site chosen in the config.
+#[derive(Debug, Clone)]
+pub struct WikiClient
+{
+    /// The wiki, as configured
+    wiki: Wiki,
+    /// The user-agent to send with every request
+    user_agent: String,
+}
+
+impl WikiClient
+{
+    /// Make a new WikiClient for the wiki in the config
+    pub fn new(config: &Config) -> WikiClient
+    {
+        WikiClient
+        {
+            wiki: config.wiki.clone(),
+            user_agent: format!("{}/{}", config.user, ::VERSION),
+        }
+    }
+
+    /// Make a GET request to the API, and deserialize the JSON response
+    pub fn query<T: DeserializeOwned>(&self, client: &Client, params: &[(&str, &str)])
+        -> Result<T, WikiError>
+    {
+        let mut url = self.wiki.api.clone();
+        url.query_pairs_mut()
+            .extend_pairs(params)
+            .append_pair("errorformat", "plaintext");
+
+        let res = client.get(url.clone())
+            .header(USER_AGENT, self.user_agent.as_str())
+            .send()?;
+
+        if res.status() != StatusCode::OK
+        {
+            return Err(WikiError::StatusError(res.status()));
+        }
+
+        let mut json: Value = res.json()?;
+
+        // a refused request still comes back as 200, with errors instead of a result
+        let errors: Vec<ApiMessage> = match json.get_mut("errors").map(Value::take)
+        {
+            Some(errors) => from_value(errors)?,
+            None => Vec::new(),
+        };
+        if let Some(err) = errors.into_iter().next()
+        {
+            return Err(WikiError::from(err));
+        }
+
+        // wikis that don't know `errorformat` still send the old style of error
+        if let Some(err) = json.get_mut("error").map(Value::take)
+        {
+            return Err(WikiError::from(from_value::<ApiMessage>(err)?));
+        }
+
+        let warnings: Vec<ApiMessage> = match json.get_mut("warnings").map(Value::take)
+        {
+            Some(warnings) => from_value(warnings.clone()).unwrap_or_else(|_|
+            {
+                // the old style is a map by module, which is only worth logging
+                warn!("The wiki warned {} for {}", warnings, url);
+                Vec::new()
+            }),
+            None => Vec::new(),
+        };
+        for warning in warnings
+        {
+            if SERIOUS_WARNINGS.contains(&warning.code.as_str())
+            {
+                error!("The wiki warned \"{}\" ({}) for {}", warning.text, warning.code, url);
+                return Err(WikiError::ApiWarning
+                {
+                    code: warning.code,
+                    info: warning.text,
+                });
+            }
+
+            warn!("The wiki warned \"{}\" ({}) for {}", warning.text, warning.code, url);
+        }
+
+        Ok(from_value(json)?)
+    }
+
+    /// Get the category that marks disambiguation pages, if there is one
+    pub fn disambiguation_category(&self) -> Option<&str>
+    {
+        self.wiki.disambiguation_category.as_deref()
+    }
+
+    /// Get the widest an image can be before a thumbnail is linked instead
+    pub fn thumb_width(&self) -> Option<u32>
+    {
+        self.wiki.thumb_width
+    }
+
+    /// Check whether files that aren't images should be left out
+    pub fn only_images(&self) -> bool
+    {
+        self.wiki.only_images
+    }
+
+    /// Get the URL of a page on the wiki
+    pub fn page_url(&self, title: &str) -> String
+    {
+        self.wiki.article_path.replace("$1", &title.replace(' ', "_"))
+    }
+}
+
+// ==============================
+// Serde structs below
+// ==============================
+
+// an error or warning, in either the plaintext or the old format
+#[derive(Deserialize)]
+struct ApiMessage
+{
+    code: String,
+    #[serde(default, alias = "info", alias = "*")]
+    text: String,
+}
+
+impl From<ApiMessage> for WikiError
+{
+    fn from(err: ApiMessage) -> WikiError
+    {
+        WikiError::ApiError
+        {
+            code: err.code,
+            info: err.text,
+        }
+    }
+}
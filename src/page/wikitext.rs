@@ -0,0 +1,106 @@
+use reqwest::blocking::Client;
+
+use super::{WikiClient, WikiError};
+use super::query::{query_titles, QueryPage, QueryResult};
+
+/// The wikitext source of a page
+#[derive(Debug)]
+pub struct Wikitext
+{
+    /// The title of the page
+    pub title: String,
+    /// The wikitext of the latest revision
+    pub text: String,
+}
+
+impl Wikitext
+{
+    pub fn get(client: &Client, wiki: &WikiClient, titles: &[String])
+        -> Result<Vec<Wikitext>, WikiError>
+    {
+        let res = query_titles(client, wiki, &[
+                ("prop", "revisions"),
+                ("rvprop", "content"),
+                ("rvslots", "main"),
+            ], titles)?;
+
+        Ok(Wikitext::parse_response(res))
+    }
+
+    fn parse_response(res: QueryResult<PageJSON>) -> Vec<Wikitext>
+    {
+        let mut texts = Vec::new();
+
+        for page in res.pages
+        {
+            let title = match page.title
+            {
+                Some(title) => title,
+                None => continue,
+            };
+
+            // older wikis don't have slots, and put the content right in the revision
+            let text = page.revisions
+                .and_then(|revs| revs.into_iter().next())
+                .and_then(|rev| rev.slots
+                    .and_then(|slots| slots.main.content)
+                    .or(rev.content));
+
+            if let Some(text) = text
+            {
+                texts.push(Wikitext
+                {
+                    title,
+                    text,
+                });
+            }
+        }
+
+        texts
+    }
+}
+
+// ==============================
+// Serde structs below
+// ==============================
+
+#[derive(Deserialize)]
+struct PageJSON
+{
+    title: Option<String>,
+    revisions: Option<Vec<Revision>>,
+}
+
+impl QueryPage for PageJSON
+{
+    fn title(&self) -> Option<&str>
+    {
+        self.title.as_deref()
+    }
+
+    fn merge(&mut self, more: PageJSON)
+    {
+        self.revisions = self.revisions.take().or(more.revisions);
+    }
+}
+
+#[derive(Deserialize)]
+struct Revision
+{
+    slots: Option<Slots>,
+    #[serde(alias = "*")]
+    content: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Slots
+{
+    main: Slot,
+}
+
+#[derive(Deserialize)]
+struct Slot
+{
+    #[serde(alias = "*")]
+    content: Option<String>,
+}
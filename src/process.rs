@@ -1,73 +1,388 @@
-use uuid::Uuid;
+use std::collections::HashMap;
 
-use secrets::maintainer;
+use reqwest::blocking::Client;
+
+use config::{Config, InfoField};
 use reddit::Message;
-use friend::Friend;
-use page::Page;
+use friend::{Friend, Media};
+use page::{Page, WikiClient, WikiError, partial_page, image_url, wikitext};
+use page::cache::PageCache;
+use page::index::TitleIndex;
+use page::suggestion::Suggestion;
+use page::infobox::FriendInfo;
+
+/// What was found on the wiki for one message
+#[derive(Debug)]
+pub struct Lookup
+{
+    /// The pages for the message's friends
+    pub pages: Vec<Page>,
+    /// Pages that might be what the user meant, by the title of each friend
+    /// that wasn't found
+    pub suggestions: HashMap<String, Vec<Suggestion>>,
+}
 
 /// Find friends in messages
-pub fn find_friends(messages: Vec<Message>, user: &str) -> (Vec<(Message, Vec<Uuid>)>, Vec<Friend>)
+///
+/// Messages that don't ask for any friends are left out. If there is an index,
+/// friend titles are corrected to the ones the wiki uses.
+pub fn find_friends(
+    messages: Vec<Message>,
+    user: &str,
+    medias: &[Media],
+    index: Option<&TitleIndex>)
+    -> Vec<(Message, Vec<Friend>)>
 {
-    let mut message_ids = Vec::new();
-    let mut friends = Vec::new();
+    let mut requests = Vec::new();
 
     for message in messages
     {
-        if let Some(mut found) = Friend::find(&message.body, user)
+        if let Some(mut found) = Friend::find(&message.body, user, medias)
+        {
+            if let Some(index) = index
+            {
+                for friend in found.iter_mut()
+                {
+                    if let Some(title) = index.correct(friend)
+                    {
+                        info!("Corrected \"{}\" to \"{}\"", friend.title, title);
+                        friend.title = title;
+                    }
+                }
+            }
+
+            if !found.is_empty()
+            {
+                requests.push((message, found));
+            }
+        }
+    }
+
+    requests
+}
+
+/// Find the wiki pages for the friends in each message
+///
+/// The results are in the same order as the messages. Every message is looked up
+/// together at first, but if that fails, each message is looked up on its own so
+/// that one bad message doesn't stop the others from getting replies.
+pub fn find_pages(
+    client: &Client,
+    wiki: &WikiClient,
+    cache: &mut PageCache,
+    index: Option<&TitleIndex>,
+    requests: &[(Message, Vec<Friend>)])
+    -> Vec<Result<Lookup, WikiError>>
+{
+    let friends = requests.iter()
+        .flat_map(|(_, friends)| friends.iter().cloned())
+        .collect::<Vec<_>>();
+
+    let pages = match get_pages(client, wiki, cache, &friends)
+    {
+        Ok(pages) =>
+        {
+            info!("Made {} pages", pages.len());
+
+            requests.iter()
+                .map(|(_, friends)| Ok(pages.iter()
+                    .filter(|page| friends.iter().any(|f| page.friends.contains(&f.id)))
+                    .cloned()
+                    .collect()))
+                .collect::<Vec<_>>()
+        },
+        Err(e) if requests.len() == 1 => vec![Err(e)],
+        Err(e) =>
+        {
+            warn!("Looking up {} messages together failed with \"{:?}\", \
+                looking them up one at a time", requests.len(), e);
+
+            requests.iter()
+                .map(|(_, friends)| get_pages(client, wiki, cache, friends))
+                .collect::<Vec<_>>()
+        },
+    };
+
+    requests.iter()
+        .zip(pages)
+        .map(|((_, friends), pages)| pages.map(|pages| Lookup
+        {
+            suggestions: suggest(client, wiki, cache, index, friends, &pages),
+            pages,
+        }))
+        .collect()
+}
+
+// get pages from the cache, and from the wiki for anything that isn't cached
+fn get_pages(client: &Client, wiki: &WikiClient, cache: &mut PageCache, friends: &[Friend])
+    -> Result<Vec<Page>, WikiError>
+{
+    let (mut pages, misses) = cache.lookup(friends);
+
+    if !misses.is_empty()
+    {
+        let found = fetch_pages(client, wiki, &misses)?;
+        cache.store(&misses, &found);
+        pages.extend(found);
+    }
+
+    // two titles might lead to the same page, one cached and one not
+    Ok(Page::merge(pages))
+}
+
+fn fetch_pages(client: &Client, wiki: &WikiClient, friends: &[Friend]) -> Result<Vec<Page>, WikiError>
+{
+    let mut partials = partial_page::PartialPage::get(client, wiki, friends)?;
+
+    // disambiguation pages are just lists, there's nothing to excerpt
+    let titles = partials.iter()
+        .filter(|partial| partial.subpages.is_none())
+        .map(|partial| partial.title.clone())
+        .collect::<Vec<_>>();
+    // the excerpt and infobox are only nice to have, so the links go out without them
+    let texts = wikitext::Wikitext::get(client, wiki, &titles).unwrap_or_else(|e|
+    {
+        warn!("Could not read the text of {:?}: {:?}", titles, e);
+        Vec::new()
+    });
+
+    // the infobox is needed to pick the image, so the text comes first
+    for partial in partials.iter_mut()
+    {
+        if let Some(text) = texts.iter().find(|text| text.title == partial.title)
         {
-            message_ids.push((message, found.iter().map(|f| f.id).collect::<Vec<_>>()));
+            partial.read_text(&text.text);
+        }
+
+        partial.select_image();
 
-            friends.append(&mut found);
+        if let (Some(image), Some(strategy)) = (&partial.image_title, partial.image_strategy)
+        {
+            info!("Chose \"{}\" for \"{}\" by {:?}", image, partial.title, strategy);
         }
     }
 
-    (message_ids, friends)
+    let images = image_url::ImageUrl::get(client, wiki, &partials)?;
+
+    Ok(Page::make(partials, &images, friends))
 }
 
-/// Format replies to messages
-pub fn make_replies(messages: Vec<(Message, Vec<Uuid>)>, pages: Vec<Page>) -> Vec<(String, String)>
+// find suggestions for every friend that doesn't have a page
+//
+// Suggestions are only nice to have, so a failed search is just left out. The
+// index is tried first, since it doesn't need a request, then the suggestions
+// cached for a title with no page. Anything searched for is cached.
+fn suggest(
+    client: &Client,
+    wiki: &WikiClient,
+    cache: &mut PageCache,
+    index: Option<&TitleIndex>,
+    friends: &[Friend],
+    pages: &[Page])
+    -> HashMap<String, Vec<Suggestion>>
 {
-    let mut replies = Vec::new();
+    let mut suggestions = HashMap::new();
+    let mut searched = HashMap::new();
 
-    for (message, ids) in messages
+    for friend in friends.iter()
+        .filter(|f| !pages.iter().any(|page| page.friends.contains(&f.id)))
     {
-        let mut fmt = String::new();
-        for page in pages.iter()
+        if suggestions.contains_key(&friend.title) || searched.contains_key(&friend.title)
+        {
+            continue;
+        }
+
+        let indexed = index
+            .map(|index| Suggestion::from_index(wiki, index, &friend.title))
+            .unwrap_or_default();
+        if !indexed.is_empty()
         {
-            if page.friends.iter().any(|id| ids.contains(id))
+            suggestions.insert(friend.title.clone(), indexed);
+            continue;
+        }
+
+        if let Some(cached) = cache.suggestions(&friend.title)
+        {
+            suggestions.insert(friend.title.clone(), cached.to_vec());
+            continue;
+        }
+
+        match Suggestion::get(client, wiki, &friend.title)
+        {
+            Ok(found) =>
             {
-                fmt.push_str(&format!("[{title}]({link})",
-                    title = escape_md(&page.title),
-                    link = page.url));
+                searched.insert(friend.title.clone(), found);
+            },
+            Err(e) => warn!("Could not search for \"{}\": {:?}", friend.title, e),
+        }
+    }
 
-                if let &Some(ref image) = &page.image
-                {
-                    fmt.push_str(&format!(" ([pic]({}))", image));
-                }
+    cache.store_suggestions(&searched);
+    suggestions.extend(searched);
+
+    suggestions
+}
+
+/// Format a reply to a message
+///
+/// The reply links every page that was found, and lists the titles of the friends
+/// that weren't, so the user can see how their request was read.
+pub fn make_reply(message: &Message, friends: &[Friend], lookup: &Lookup, config: &Config)
+    -> String
+{
+    let policy = config.subreddits.policy(&message.subreddit);
 
-                fmt.push_str("\n\n"); // two newlines for one visible newline
+    let mut fmt = String::new();
+    for page in lookup.pages.iter()
+        .filter(|page| friends.iter().any(|f| page.friends.contains(&f.id)))
+        .take(policy.max_links.unwrap_or(usize::MAX))
+    {
+        fmt.push_str(&format!("[{title}]({link})",
+            title = escape_md(&page.title),
+            link = page.url));
+
+        if let Some(subpages) = &page.subpages
+        {
+            // a list of the real pages, rather than a picture of one of them
+            if !subpages.is_empty()
+            {
+                let links = subpages.iter()
+                    .map(|s| format!("[{}]({})", escape_md(s.label()), s.url))
+                    .collect::<Vec<_>>();
+
+                fmt.push_str(&format!(" could mean: {}", links.join(", ")));
+            }
+        }
+        else if policy.images
+        {
+            if let Some(image) = &page.image
+            {
+                fmt.push_str(&format!(" ([pic]({}))", image));
             }
         }
 
-        if fmt.len() == 0
+        fmt.push_str("\n\n"); // two newlines for one visible newline
+
+        if policy.excerpts
         {
-            continue;
+            if let Some(excerpt) = &page.excerpt
+            {
+                fmt.push_str(&format!("> {}\n\n", escape_md(excerpt)));
+            }
+        }
+
+        if policy.info
+        {
+            if let Some(info) = &page.info
+            {
+                fmt.push_str(&info_table(info, &config.info_fields));
+            }
+        }
+    }
+
+    let mut missing: Vec<&str> = Vec::new();
+    for friend in friends.iter()
+        .filter(|f| !lookup.pages.iter().any(|page| page.friends.contains(&f.id)))
+    {
+        if !missing.contains(&friend.title.as_str())
+        {
+            missing.push(&friend.title);
+        }
+    }
+
+    for title in missing
+    {
+        fmt.push_str(&format!("I couldn't find a page called \"{}\".", escape_md(title)));
+
+        if let Some(suggestions) = lookup.suggestions.get(title)
+        {
+            if !suggestions.is_empty()
+            {
+                let links = suggestions.iter()
+                    .map(|s| format!("[{}]({})", escape_md(&s.title), s.url))
+                    .collect::<Vec<_>>();
+
+                fmt.push_str(&format!(" Did you mean {}?", links.join(", ")));
+            }
         }
 
-        fmt.push_str(&format!("---\n\n\
-            ^^I'm ^^a ^^bot ^^friend! ^^Contact ^^\\/u/{maintainer} \
-            ^^if ^^you ^^have ^^any ^^questions ^^or ^^concerns. \
-            ^^View ^^my ^^[code]({code}), ^^and ^^my \
-            ^^[subreddit]({subreddit}). ^^Version ^^{version}.",
-            maintainer = maintainer(),
-            code = "https://github.com/Matthew-Maclean/japari-librarian",
-            subreddit = "https://www.reddit.com/r/japari_librarian/",
-            version = ::VERSION));
-
-        replies.push((message.name, fmt));
+        fmt.push_str("\n\n");
     }
 
-    replies
+    let mut unknown: Vec<&str> = Vec::new();
+    for media in friends.iter().filter_map(|f| f.unknown_media.as_ref())
+    {
+        if !unknown.contains(&media.as_str())
+        {
+            unknown.push(media);
+        }
+    }
+
+    if !unknown.is_empty()
+    {
+        let unknown = unknown.iter()
+            .map(|media| format!("\"{}\"", escape_md(media)))
+            .collect::<Vec<_>>();
+        let known = config.medias.iter()
+            .map(|media| escape_md(&media.label))
+            .collect::<Vec<_>>();
+
+        fmt.push_str(&format!("I don't know the media {}, so I looked without it. \
+            The medias I know are: {}.\n\n", unknown.join(", "), known.join(", ")));
+    }
+
+    fmt.push_str(&footer(config));
+
+    fmt
+}
+
+/// Format a reply to a message that couldn't be looked up
+pub fn make_failed_reply(config: &Config) -> String
+{
+    let mut fmt = String::from("Sorry, I couldn't look up your friends in the library. \
+        Please try again later!\n\n");
+
+    fmt.push_str(&footer(config));
+
+    fmt
+}
+
+// a one row table of the facts the page has, or nothing if it has none
+fn info_table(info: &FriendInfo, fields: &[InfoField]) -> String
+{
+    let facts = fields.iter()
+        .filter_map(|field| info.get(&field.field).map(|value| (&field.label, value)))
+        .collect::<Vec<_>>();
+
+    if facts.is_empty()
+    {
+        return String::new();
+    }
+
+    let labels = facts.iter().map(|&(label, _)| escape_table(label)).collect::<Vec<_>>();
+    let values = facts.iter().map(|&(_, value)| escape_table(value)).collect::<Vec<_>>();
+    let aligns = facts.iter().map(|_| ":-").collect::<Vec<_>>();
+
+    // pipes at both ends, or a table with one column isn't a table on reddit
+    format!("|{}|\n|{}|\n|{}|\n\n", labels.join("|"), aligns.join("|"), values.join("|"))
+}
+
+fn escape_table(source: &str) -> String
+{
+    escape_md(source).replace('|', "\\|")
+}
+
+fn footer(config: &Config) -> String
+{
+    format!("---\n\n\
+        ^^I'm ^^a ^^bot ^^friend! ^^Contact ^^\\/u/{maintainer} \
+        ^^if ^^you ^^have ^^any ^^questions ^^or ^^concerns. \
+        ^^View ^^my ^^[code]({code}), ^^and ^^my \
+        ^^[subreddit]({subreddit}). ^^Version ^^{version}.",
+        maintainer = config.maintainer,
+        code = "https://github.com/Matthew-Maclean/japari-librarian",
+        subreddit = "https://www.reddit.com/r/japari_librarian/",
+        version = ::VERSION)
 }
 
 // kind of rudimentary, but probably OK
@@ -0,0 +1,238 @@
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+
+use reqwest::{blocking::Client, StatusCode, Url};
+use reqwest::header::USER_AGENT;
+use uuid::Uuid;
+
+use config::{Auth, Config};
+use state;
+
+use super::{RedditError, Session};
+use super::session::token_path;
+
+/// Authorize the bot's account, and save the refresh token for `Session`
+///
+/// This prints a link to reddit's authorization page, then listens on the
+/// redirect URI until reddit sends the browser back with a code. The code is
+/// exchanged for a refresh token, which is saved in the state directory.
+/// Returns where the token was saved.
+pub fn authorize(client: &Client, config: &Config) -> Result<PathBuf, AuthorizeError>
+{
+    let redirect_uri = match config.auth
+    {
+        Auth::RefreshToken { ref redirect_uri } => redirect_uri,
+        Auth::Password(_) => return Err(AuthorizeError::PasswordAuth),
+    };
+
+    // listen first, so the browser can't come back before we're ready
+    let listener = TcpListener::bind(&*redirect_uri.socket_addrs(|| None)?)?;
+
+    // reddit sends this back, to prove the redirect is for this link
+    let state = Uuid::new_v4().simple().to_string();
+    let link = Url::parse_with_params("https://www.reddit.com/api/v1/authorize", &[
+            ("client_id", config.id.as_str()),
+            ("response_type", "code"),
+            ("state", state.as_str()),
+            ("redirect_uri", redirect_uri.as_str()),
+            ("duration", "permanent"),
+            ("scope", SCOPES),
+        ])
+        .expect("the authorize URL is always valid");
+
+    println!("Open this link while logged in as /u/{}, and allow access:\n\n{}\n",
+        config.user, link);
+    println!("Waiting for reddit on {}", redirect_uri);
+
+    let code = loop
+    {
+        let (stream, _) = listener.accept()?;
+
+        // browsers like to ask for other things, like a favicon
+        if let Some(code) = read_redirect(stream, redirect_uri, &state)?
+        {
+            break code?;
+        }
+    };
+
+    info!("Exchanging the code for a refresh token");
+
+    let res = client.post("https://www.reddit.com/api/v1/access_token")
+        .header(USER_AGENT, Session::new(config).user_agent())
+        .basic_auth(config.id.clone(), Some(config.secret.clone()))
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code.as_str()),
+            ("redirect_uri", redirect_uri.as_str()),
+        ])
+        .send()?;
+
+    let token = match res.status()
+    {
+        StatusCode::OK => match res.json::<TokenResponse>()?
+        {
+            TokenResponse { refresh_token: Some(token), .. } => token,
+            TokenResponse { error: Some(error), .. } => return Err(AuthorizeError::Denied(error)),
+            _ => return Err(AuthorizeError::Denied("no refresh token".to_owned())),
+        },
+        StatusCode::UNAUTHORIZED => return Err(AuthorizeError::Reddit(RedditError::Unauthorized)),
+        code => return Err(AuthorizeError::Reddit(RedditError::OtherStatus(code))),
+    };
+
+    let path = token_path(config);
+    save_token(&path, &token)?;
+
+    Ok(path)
+}
+
+/// An error that might occur while authorizing the bot
+#[derive(Debug)]
+pub enum AuthorizeError
+{
+    /// The config uses the account password, which doesn't need authorizing
+    PasswordAuth,
+    /// The redirect couldn't be listened for, or the token couldn't be saved
+    Io(io::Error),
+    /// Access was declined, or reddit refused the code, with reddit's reason
+    Denied(String),
+    /// The redirect didn't come from the printed link
+    StateMismatch,
+    /// The request for the refresh token failed
+    Reddit(RedditError),
+}
+
+impl fmt::Display for AuthorizeError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self
+        {
+            AuthorizeError::PasswordAuth => write!(f,
+                "the config uses the account password, set \"auth\" to \"refresh_token\" first"),
+            AuthorizeError::Io(err) => write!(f, "{}", err),
+            AuthorizeError::Denied(reason) => write!(f,
+                "reddit did not authorize the bot: {}", reason),
+            AuthorizeError::StateMismatch => write!(f,
+                "the redirect was not for the printed link"),
+            AuthorizeError::Reddit(err) => write!(f,
+                "could not get the refresh token: {:?}", err),
+        }
+    }
+}
+
+impl From<io::Error> for AuthorizeError
+{
+    fn from(err: io::Error) -> AuthorizeError
+    {
+        AuthorizeError::Io(err)
+    }
+}
+
+impl From<::reqwest::Error> for AuthorizeError
+{
+    fn from(err: ::reqwest::Error) -> AuthorizeError
+    {
+        AuthorizeError::Reddit(RedditError::from(err))
+    }
+}
+
+// read a request to the listener, and answer it
+//
+// Returns `None` if it wasn't for the redirect URI, or the outcome of
+// authorizing if it was.
+fn read_redirect(stream: TcpStream, redirect_uri: &Url, state: &str)
+    -> io::Result<Option<Result<String, AuthorizeError>>>
+{
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    // like "GET /authorize_callback?state=...&code=... HTTP/1.1"
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+
+    // the rest is headers, read so closing the connection doesn't reset it
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2
+    {
+        line.clear();
+    }
+
+    let url = request.split_whitespace()
+        .nth(1)
+        .and_then(|target| redirect_uri.join(target).ok());
+    let url = match url
+    {
+        Some(ref url) if url.path() == redirect_uri.path() => url,
+        _ =>
+        {
+            respond(&stream, "404 Not Found", "Not found")?;
+            return Ok(None);
+        }
+    };
+
+    let param = |name: &str| url.query_pairs()
+        .find(|(k, _)| k == name)
+        .map(|(_, v)| v.into_owned());
+
+    let outcome = if param("state").as_deref() != Some(state)
+    {
+        Err(AuthorizeError::StateMismatch)
+    }
+    else if let Some(error) = param("error")
+    {
+        Err(AuthorizeError::Denied(error))
+    }
+    else
+    {
+        param("code").ok_or_else(|| AuthorizeError::Denied("no code".to_owned()))
+    };
+
+    match outcome
+    {
+        Ok(_) => respond(&stream, "200 OK",
+            "The bot is authorized, this window can be closed.")?,
+        Err(ref e) => respond(&stream, "400 Bad Request",
+            &format!("The bot was not authorized: {}", e))?,
+    }
+
+    Ok(Some(outcome))
+}
+
+fn respond(mut stream: &TcpStream, status: &str, body: &str) -> io::Result<()>
+{
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: text/plain; charset=utf-8\r\n\
+        Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, body.len(), body)?;
+    stream.flush()
+}
+
+// the token is as good as the password, so only the bot's user can read it
+fn save_token(path: &Path, token: &str) -> io::Result<()>
+{
+    state::replace(path, |file|
+    {
+        #[cfg(unix)]
+        {
+            use std::fs::Permissions;
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(Permissions::from_mode(0o600))?;
+        }
+
+        file.write_all(token.as_bytes())
+    })
+}
+
+// reading and marking messages, and commenting
+const SCOPES: &str = "privatemessages submit";
+
+// ==============================
+// Serde structs below
+// ==============================
+
+#[derive(Deserialize)]
+struct TokenResponse
+{
+    refresh_token: Option<String>,
+    error: Option<String>,
+}
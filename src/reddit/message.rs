@@ -1,10 +1,9 @@
-use reqwest::blocking::Client;
-use reqwest::header::USER_AGENT;
+use reqwest::blocking::{Client, RequestBuilder};
 
 use super::{RedditError, Session};
 
 /// A reddit inbox message
-#[derive(Debug, Deserialize, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct Message
 {
     /// The fullname of the message
@@ -35,8 +34,6 @@ impl Message
             limit: u64) // it is expected that the caller will keep track of the limit
             -> Result<Vec<Message>, RedditError>
         {
-            session.prepare();
-
             let url = if let Some(after) = after
             {
                 ::reqwest::Url::parse_with_params(
@@ -53,10 +50,7 @@ impl Message
                     &[("limit", &limit.to_string())]).unwrap()
             };
 
-            let res = client.get(url)
-                .header(USER_AGENT, session.user_agent())
-                .bearer_auth(session.bearer(client)?)
-                .send()?;
+            let res = session.send(client, |client| client.get(url.clone()))?;
 
             use reqwest::StatusCode;
 
@@ -76,7 +70,7 @@ impl Message
         {
             Some(lim) => while (messages.len() as u64) < lim
             {
-                let after = if messages.len() != 0
+                let after = if !messages.is_empty()
                 {
                     Some(messages[messages.len() - 1].name.clone())
                 }
@@ -94,7 +88,7 @@ impl Message
                     get_messages(client, session, after, MAX_MESSAGES)?
                 };
 
-                if msgs.len() == 0
+                if msgs.is_empty()
                 {
                     break;
                 }
@@ -103,7 +97,7 @@ impl Message
             },
             None => loop
             {
-                let after = if messages.len() != 0
+                let after = if !messages.is_empty()
                 {
                     Some(messages[messages.len() - 1].name.clone())
                 }
@@ -114,7 +108,7 @@ impl Message
 
                 let mut msgs = get_messages(client, session, after, MAX_MESSAGES)?;
 
-                if msgs.len() == 0
+                if msgs.is_empty()
                 {
                     break;
                 }
@@ -130,7 +124,7 @@ impl Message
     pub fn mark_read(client: &Client, session: &mut Session, messages: &[Message])
         -> Result<(), RedditError>
     {
-        if messages.len() == 0
+        if messages.is_empty()
         {
             return Ok(());
         }
@@ -143,18 +137,12 @@ impl Message
             names.push(',');
         }
 
-        if names.len() > 0
+        if !names.is_empty()
         {
             names.pop(); // remove trailing comma
         }
 
-        session.prepare();
-
-        let res = client.post("https://oauth.reddit.com/api/read_message")
-            .header(USER_AGENT, session.user_agent())
-            .bearer_auth(session.bearer(client)?)
-            .body(format!("id={}", names))
-            .send()?;
+        let res = session.send(client, |client| read_request(client, &names))?;
 
         use reqwest::StatusCode;
 
@@ -167,9 +155,34 @@ impl Message
     }
 }
 
+fn read_request(client: &Client, names: &str) -> RequestBuilder
+{
+    client.post("https://oauth.reddit.com/api/read_message")
+        .form(&[("id", names)])
+}
+
 #[derive(Deserialize)]
 struct MessageResponse{ data: MessageList }
 #[derive(Deserialize)]
 struct MessageList{ children: Vec<MessageContainer> }
 #[derive(Deserialize)]
 struct MessageContainer{ data: Message }
+
+#[cfg(test)]
+mod tests
+{
+    use reqwest::blocking::Client;
+
+    use super::read_request;
+
+    #[test]
+    fn read_body_is_form_encoded()
+    {
+        let req = read_request(&Client::new(), "t4_abc,t1_a b&c=d+e%f")
+            .build()
+            .unwrap();
+
+        assert_eq!(req.body().and_then(|b| b.as_bytes()),
+            Some(&b"id=t4_abc%2Ct1_a+b%26c%3Dd%2Be%25f"[..]));
+    }
+}
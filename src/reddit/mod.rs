@@ -1,12 +1,19 @@
 pub mod session;
 pub mod message;
 pub mod reply;
+pub mod queue;
+pub mod authorize;
+
+use std::io;
+use std::time::Duration;
 
 use reqwest::{Error, StatusCode};
 
 pub use self::session::Session;
 pub use self::message::Message;
 pub use self::reply::reply;
+pub use self::queue::ReplyQueue;
+pub use self::authorize::authorize;
 
 /// An error that might occur during a reddit request
 #[derive(Debug)]
@@ -14,12 +21,59 @@ pub enum RedditError
 {
     /// Bad client ID or secret
     Unauthorized,
-    /// Bad username or password
+    /// Bad username or password, or a revoked refresh token
     BadCredentials,
+    /// The refresh token hasn't been saved by the `authorize` command,
+    /// or couldn't be read
+    NoRefreshToken(Option<io::Error>),
     /// Another status that isn't 200 OK
     OtherStatus(StatusCode),
     /// Another error
     OtherError(Error),
+    /// Too many comments have been made recently
+    ///
+    /// Reddit's message says how long to wait, if it could be understood.
+    RateLimit
+    {
+        message: String,
+        wait: Option<Duration>,
+    },
+    /// The thread is locked, so it can't be replied to
+    ThreadLocked,
+    /// The comment being replied to was deleted
+    DeletedComment,
+    /// The thread is archived, so it can't be replied to
+    TooOld,
+    /// Another error from the reddit API, with its code and message
+    ApiError
+    {
+        code: String,
+        message: String,
+    },
+}
+
+impl RedditError
+{
+    /// Make an error from an error code and message in an API response
+    pub fn from_api(code: String, message: String) -> RedditError
+    {
+        match code.as_str()
+        {
+            "RATELIMIT" => RedditError::RateLimit
+            {
+                wait: ratelimit_wait(&message),
+                message,
+            },
+            "THREAD_LOCKED" => RedditError::ThreadLocked,
+            "DELETED_COMMENT" => RedditError::DeletedComment,
+            "TOO_OLD" => RedditError::TooOld,
+            _ => RedditError::ApiError
+            {
+                code,
+                message,
+            },
+        }
+    }
 }
 
 impl From<Error> for RedditError
@@ -29,3 +83,67 @@ impl From<Error> for RedditError
         RedditError::OtherError(err)
     }
 }
+
+// the wait in a message like "you are doing that too much. try again in 5 minutes."
+fn ratelimit_wait(message: &str) -> Option<Duration>
+{
+    let message = message.to_lowercase();
+
+    let rest = ["try again in ", "take a break for "].iter()
+        .filter_map(|lead| message.find(lead).map(|i| &message[i + lead.len()..]))
+        .next()?;
+
+    let mut words = rest.split_whitespace();
+    let n = words.next()?.parse::<u64>().ok()?;
+    let unit = words.next()?.trim_end_matches(|c: char| !c.is_alphabetic());
+
+    // a second more than asked for, since reddit rounds down
+    match unit
+    {
+        "millisecond" | "milliseconds" => Some(Duration::from_millis(n) + Duration::from_secs(1)),
+        "second" | "seconds" => Some(Duration::from_secs(n + 1)),
+        "minute" | "minutes" => Some(Duration::from_secs(n * 60 + 1)),
+        "hour" | "hours" => Some(Duration::from_secs(n * 60 * 60 + 1)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::time::Duration;
+
+    use super::ratelimit_wait;
+
+    #[test]
+    fn wait_in_minutes()
+    {
+        assert_eq!(ratelimit_wait("you are doing that too much. try again in 5 minutes."),
+            Some(Duration::from_secs(5 * 60 + 1)));
+        assert_eq!(ratelimit_wait("Looks like you've been doing that a lot. \
+            Take a break for 1 minute before trying again."),
+            Some(Duration::from_secs(61)));
+    }
+
+    #[test]
+    fn wait_in_seconds()
+    {
+        assert_eq!(ratelimit_wait("you are doing that too much. try again in 30 seconds."),
+            Some(Duration::from_secs(31)));
+    }
+
+    #[test]
+    fn wait_in_milliseconds()
+    {
+        assert_eq!(ratelimit_wait("you are doing that too much. try again in 500 milliseconds."),
+            Some(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn unparseable_wait()
+    {
+        assert_eq!(ratelimit_wait("you are doing that too much"), None);
+        assert_eq!(ratelimit_wait("try again in a few minutes."), None);
+        assert_eq!(ratelimit_wait("try again in 5 fortnights."), None);
+    }
+}
@@ -0,0 +1,61 @@
+use std::time::{Duration, Instant};
+
+use super::Message;
+
+/// Replies that have to wait for reddit's comment ratelimit
+///
+/// When reddit says the account is commenting too often, the replies are
+/// held here until the wait is over, and sent on a later cycle. Nothing
+/// sleeps, so the inbox is still read while they wait.
+#[derive(Debug, Default)]
+pub struct ReplyQueue
+{
+    held: Vec<(Message, String)>,
+    until: Option<Instant>,
+}
+
+impl ReplyQueue
+{
+    pub fn new() -> ReplyQueue
+    {
+        ReplyQueue
+        {
+            held: Vec::new(),
+            until: None,
+        }
+    }
+
+    /// Hold a reply until at least `wait` from now
+    ///
+    /// Every held reply waits for the longest wait so far, since the ratelimit
+    /// is for the whole account.
+    pub fn hold(&mut self, message: Message, body: String, wait: Duration)
+    {
+        let until = Instant::now() + wait;
+        self.until = Some(match self.until
+        {
+            Some(old) if old > until => old,
+            _ => until,
+        });
+
+        self.held.push((message, body));
+    }
+
+    /// Check if replies still have to wait
+    pub fn waiting(&self) -> bool
+    {
+        self.until.map(|until| until > Instant::now()).unwrap_or(false)
+    }
+
+    /// Take every held reply, if the wait is over
+    pub fn take_ready(&mut self) -> Vec<(Message, String)>
+    {
+        if self.waiting()
+        {
+            return Vec::new();
+        }
+
+        self.until = None;
+        self.held.drain(..).collect()
+    }
+}
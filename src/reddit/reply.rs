@@ -1,28 +1,119 @@
-use reqwest::blocking::Client;
-use reqwest::header::USER_AGENT;
+use std::time::Duration;
+
+use reqwest::blocking::{Client, RequestBuilder};
+use reqwest::StatusCode;
 
 use super::RedditError;
 use super::Session;
 
-pub fn reply(client: &Client, session: &mut Session, replies: Vec<(String, String)>)
-    -> Result<(), RedditError>
+/// Reply to a series of messages
+///
+/// Every reply is tried, even if some fail. The outcome of each is returned with
+/// the name of the message it was for, in the same order, so the caller can
+/// decide which are worth trying again. Once reddit says the account is
+/// ratelimited, the rest of the replies aren't sent, and get the same error.
+///
+/// `sending` is called with the name of each message just before its reply is
+/// sent. If it returns false, nothing more is sent, and the outcomes stop there.
+pub fn reply<F>(
+    client: &Client,
+    session: &mut Session,
+    replies: Vec<(String, String)>,
+    mut sending: F)
+    -> Vec<(String, Result<(), RedditError>)>
+    where F: FnMut(&str) -> bool
 {
+    let mut limited: Option<(String, Option<Duration>)> = None;
+    let mut outcomes = Vec::with_capacity(replies.len());
+
     for (name, body) in replies
     {
-        session.prepare();
-
-        let res = client.post("https://oauth.reddit.com/api/comment")
-            .header(USER_AGENT, session.user_agent())
-            .bearer_auth(session.bearer(client)?)
-            .body(format!(
-                "parent={name}\
-                &text={body}",
-                name = name,
-                body = body))
-            .send()?;
-
-        session.update(res.headers());
+        let result = match limited
+        {
+            Some((ref message, wait)) => Err(RedditError::RateLimit
+            {
+                message: message.clone(),
+                wait,
+            }),
+            None if !sending(&name) => break,
+            None => comment(client, session, &name, &body),
+        };
+
+        if let Err(RedditError::RateLimit { ref message, wait }) = result
+        {
+            limited = Some((message.clone(), wait));
+        }
+
+        outcomes.push((name, result));
     }
 
-    Ok(())
+    outcomes
+}
+
+fn comment(client: &Client, session: &mut Session, name: &str, body: &str)
+    -> Result<(), RedditError>
+{
+    let res = session.send(client, |client| comment_request(client, name, body))?;
+
+    match res.status()
+    {
+        StatusCode::OK => (),
+        StatusCode::UNAUTHORIZED => return Err(RedditError::Unauthorized),
+        code => return Err(RedditError::OtherStatus(code)),
+    }
+
+    // a comment that wasn't made still comes back as 200, with errors
+    match res.json::<CommentResponse>()?.json.errors.into_iter().next()
+    {
+        Some((code, message, _)) => Err(RedditError::from_api(code, message)),
+        None => Ok(()),
+    }
+}
+
+fn comment_request(client: &Client, name: &str, body: &str) -> RequestBuilder
+{
+    client.post("https://oauth.reddit.com/api/comment")
+        .form(&[
+            ("api_type", "json"),
+            ("parent", name),
+            ("text", body),
+        ])
+}
+
+// ==============================
+// Serde structs below
+// ==============================
+
+#[derive(Deserialize)]
+struct CommentResponse
+{
+    json: CommentJson,
+}
+
+#[derive(Deserialize)]
+struct CommentJson
+{
+    // the error code, a message, and the field it was about
+    #[serde(default)]
+    errors: Vec<(String, String, Option<String>)>,
+}
+
+#[cfg(test)]
+mod tests
+{
+    use reqwest::blocking::Client;
+
+    use super::comment_request;
+
+    #[test]
+    fn comment_body_is_form_encoded()
+    {
+        let req = comment_request(&Client::new(), "t1_abc",
+            "Rock & Roll + 100% = [Serval](https://example.com/?a=1&b=2)")
+            .build()
+            .unwrap();
+
+        assert_eq!(req.body().and_then(|b| b.as_bytes()), Some(&b"api_type=json&parent=t1_abc\
+            &text=Rock+%26+Roll+%2B+100%25+%3D+%5BServal%5D%28https%3A%2F%2Fexample.com%2F%3Fa%3D1%26b%3D2%29"[..]));
+    }
 }
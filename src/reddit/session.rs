@@ -1,50 +1,54 @@
 use reqwest::{blocking::Client, StatusCode};
+use reqwest::blocking::{RequestBuilder, Response};
 use reqwest::header::{HeaderName, HeaderValue, HeaderMap};
 use reqwest::header::USER_AGENT;
 
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::time::{Instant, Duration};
 
+use config::{Auth, Config};
+
 use super::RedditError;
 
 /// A reddit session
 ///
 /// This structure handles reddit authorization and ratelimiting.
-/// To make sure a request will be handled properly, call
-/// `prepare` before the request, and `update` after it.
+/// Requests should be made with `send`, which takes care of both.
 #[derive(Debug)]
 pub struct Session
 {
     id: String,
     secret: String,
     user: String,
-    pass: String,
+    grant: Grant,
 
     token: Option<String>,
     expires: Instant,
 
-    remain: u32,
+    // unknown until the first response with ratelimit headers
+    remain: Option<u32>,
     reset: Instant,
 }
 
 impl Session
 {
-    /// Make a new Session
-    pub fn new<S0, S1, S2, S3>(id: S0, secret: S1, user: S2, pass: S3) -> Session
-        where
-            S0: Into<String>,
-            S1: Into<String>,
-            S2: Into<String>,
-            S3: Into<String>,
+    /// Make a new Session from the reddit credentials in the config
+    pub fn new(config: &Config) -> Session
     {
         Session
         {
-            id: id.into(),
-            secret: secret.into(),
-            user: user.into(),
-            pass: pass.into(),
+            id: config.id.clone(),
+            secret: config.secret.clone(),
+            user: config.user.clone(),
+            grant: match config.auth
+            {
+                Auth::Password(ref pass) => Grant::Password(pass.clone()),
+                Auth::RefreshToken { .. } => Grant::RefreshToken(token_path(config)),
+            },
             token: None,
             expires: Instant::now(),
-            remain: 0,
+            remain: None,
             reset: Instant::now(),
         }
     }
@@ -58,26 +62,52 @@ impl Session
         HeaderValue::from_str(&format!("{}/{}", self.user, ::VERSION)).unwrap()
     }
 
+    /// Make an authorized request to reddit
+    ///
+    /// `build` makes the request, and the user-agent and bearer token are added
+    /// here. The ratelimit is waited on before the request and updated after it.
+    /// If reddit rejects the token, a new one is fetched and the request is made
+    /// once more, so `build` might be called twice.
+    pub fn send<F>(&mut self, client: &Client, build: F) -> Result<Response, RedditError>
+        where F: Fn(&Client) -> RequestBuilder
+    {
+        let mut retried = false;
+
+        loop
+        {
+            self.prepare();
+
+            let res = build(client)
+                .header(USER_AGENT, self.user_agent())
+                .bearer_auth(self.bearer(client)?)
+                .send()?;
+
+            self.update(res.headers());
+
+            if res.status() == StatusCode::UNAUTHORIZED && !retried
+            {
+                info!("Reddit rejected the authorization token, getting a new one");
+                self.token = None;
+                retried = true;
+                continue;
+            }
+
+            return Ok(res);
+        }
+    }
+
     /// Get a bearer token for reddit
     ///
     /// This function will re-aquire the token if it has expired, or will expire
-    /// in 90 seconds.
+    /// in 90 seconds. The token comes from the account password or the saved
+    /// refresh token, depending on the config.
     pub fn bearer(&mut self, client: &Client) -> Result<String, RedditError>
     {
         if self.token_expired()
         {
             info!("Getting an authorization token");
 
-            let res = client.post("https://www.reddit.com/api/v1/access_token")
-                .header(USER_AGENT, self.user_agent())
-                .basic_auth(self.id.clone(), Some(self.secret.clone()))
-                .body(format!(
-                    "grant_type=password\
-                    &username={user}\
-                    &password={pass}",
-                    user = self.user,
-                    pass = self.pass))
-                .send()?;
+            let res = self.token_request(client)?.send()?;
 
             self.update(res.headers()); // does a login response have ratelimit headers?
 
@@ -93,6 +123,8 @@ impl Session
                     Err(_) => return Err(RedditError::BadCredentials)
                 },
                 StatusCode::UNAUTHORIZED => return Err(RedditError::Unauthorized),
+                // a refresh token that was revoked
+                StatusCode::BAD_REQUEST => return Err(RedditError::BadCredentials),
                 code => return Err(RedditError::OtherStatus(code))
             }
         }
@@ -100,23 +132,47 @@ impl Session
         Ok(self.token.clone().unwrap())
     }
 
+    // ask for a bearer token with the password or the saved refresh token
+    fn token_request(&self, client: &Client) -> Result<RequestBuilder, RedditError>
+    {
+        let req = match self.grant
+        {
+            Grant::Password(ref pass) => client.post(ACCESS_TOKEN_URL)
+                .form(&[
+                    ("grant_type", "password"),
+                    ("username", self.user.as_str()),
+                    ("password", pass.as_str()),
+                ]),
+            Grant::RefreshToken(ref path) => client.post(ACCESS_TOKEN_URL)
+                .form(&[
+                    ("grant_type", "refresh_token"),
+                    ("refresh_token", read_token(path)?.as_str()),
+                ]),
+        };
+
+        Ok(req
+            .header(USER_AGENT, self.user_agent())
+            .basic_auth(self.id.clone(), Some(self.secret.clone())))
+    }
+
     /// Prepare for a reddit request
     ///
     /// This function will prepare for a reddit request, and one more
     /// possible request to re-aquire a bearer token.
-    pub fn prepare(&self)
+    fn prepare(&self)
     {
         // allow for the request and a possible re-authorization
-        if self.remain < 2
+        match self.remain
         {
-            self.wait_for_reset();
+            Some(remain) if remain < 2 => self.wait_for_reset(),
+            _ => (),
         }
     }
 
     /// Update ratelimit values
     ///
     /// Returns true if the ratelimit values were updated successfully.
-    pub fn update(&mut self, headers: &HeaderMap) -> bool
+    fn update(&mut self, headers: &HeaderMap) -> bool
     {
         let rate_remain_header = HeaderName::from_static(
             X_RATELIMIT_REMAINING);
@@ -168,7 +224,7 @@ impl Session
             return false;
         };
 
-        self.remain = remain;
+        self.remain = Some(remain);
         self.reset = reset;
 
         true
@@ -197,8 +253,34 @@ impl Session
     }
 }
 
-const X_RATELIMIT_REMAINING: &'static str = "x-ratelimit-remaining";
-const X_RATELIMIT_RESET: &'static str     = "x-ratelimit-reset";
+/// Where the refresh token from the `authorize` command is kept
+pub fn token_path(config: &Config) -> PathBuf
+{
+    config.state_dir.join("refresh_token")
+}
+
+// read every time, so the bot doesn't need a restart after authorizing
+fn read_token(path: &Path) -> Result<String, RedditError>
+{
+    match fs::read_to_string(path)
+    {
+        Ok(ref token) if token.trim().is_empty() => Err(RedditError::NoRefreshToken(None)),
+        Ok(token) => Ok(token.trim().to_owned()),
+        Err(e) => Err(RedditError::NoRefreshToken(Some(e))),
+    }
+}
+
+// how the bearer token is asked for
+#[derive(Debug)]
+enum Grant
+{
+    Password(String),
+    RefreshToken(PathBuf),
+}
+
+const ACCESS_TOKEN_URL: &str = "https://www.reddit.com/api/v1/access_token";
+const X_RATELIMIT_REMAINING: &str = "x-ratelimit-remaining";
+const X_RATELIMIT_RESET: &str     = "x-ratelimit-reset";
 
 #[derive(Deserialize)]
 struct LoginResponse
@@ -206,3 +288,61 @@ struct LoginResponse
     access_token: String,
     expires_in: u64,
 }
+
+#[cfg(test)]
+mod tests
+{
+    use std::env;
+    use std::fs;
+    use std::time::Instant;
+
+    use reqwest::blocking::Client;
+
+    use super::{Grant, Session};
+
+    fn session(grant: Grant) -> Session
+    {
+        Session
+        {
+            id: "id".to_owned(),
+            secret: "secret".to_owned(),
+            user: "bot".to_owned(),
+            grant,
+            token: None,
+            expires: Instant::now(),
+            remain: None,
+            reset: Instant::now(),
+        }
+    }
+
+    fn token_body(session: &Session) -> Vec<u8>
+    {
+        let req = session.token_request(&Client::new())
+            .unwrap()
+            .build()
+            .unwrap();
+
+        req.body().and_then(|b| b.as_bytes()).unwrap().to_owned()
+    }
+
+    #[test]
+    fn password_is_form_encoded()
+    {
+        let session = session(Grant::Password("Rock & Roll+100%=x".to_owned()));
+
+        assert_eq!(token_body(&session),
+            &b"grant_type=password&username=bot&password=Rock+%26+Roll%2B100%25%3Dx"[..]);
+    }
+
+    #[test]
+    fn refresh_token_is_form_encoded()
+    {
+        let path = env::temp_dir().join("librarian-session-test-token");
+        fs::write(&path, "a&b+c%d=e f\n").unwrap();
+
+        let body = token_body(&session(Grant::RefreshToken(path.clone())));
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(body, &b"grant_type=refresh_token&refresh_token=a%26b%2Bc%25d%3De+f"[..]);
+    }
+}
@@ -0,0 +1,137 @@
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use uuid::Uuid;
+
+use page::WikiError;
+use reddit::RedditError;
+
+/// How failed messages are retried
+///
+/// Each failure waits twice as long as the last before the message is tried
+/// again, up to `max_delay`, with some random jitter so that a batch of
+/// failures doesn't all come back at once.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Backoff
+{
+    /// The number of attempts before a message is given up on
+    pub max_attempts: u32,
+    /// The delay after the first failure, in seconds
+    pub base_delay: u64,
+    /// The longest delay between attempts, in seconds
+    pub max_delay: u64,
+}
+
+impl Backoff
+{
+    /// Get the delay before the next attempt, after `attempts` failed attempts
+    pub fn delay(&self, attempts: u32) -> Duration
+    {
+        let exp = attempts.saturating_sub(1).min(32);
+        let delay = self.base_delay
+            .saturating_mul(1 << exp)
+            .min(self.max_delay);
+
+        // somewhere between half and all of the full delay
+        let half = delay / 2;
+        Duration::from_secs(half + jitter(delay - half + 1))
+    }
+}
+
+impl Default for Backoff
+{
+    fn default() -> Backoff
+    {
+        Backoff
+        {
+            max_attempts: 5,
+            base_delay: 30,
+            max_delay: 60 * 60,
+        }
+    }
+}
+
+/// An error that might go away if the request is made again
+pub trait Retryable
+{
+    /// Check if the request that made this error is worth trying again
+    fn retryable(&self) -> bool;
+}
+
+impl Retryable for WikiError
+{
+    fn retryable(&self) -> bool
+    {
+        match self
+        {
+            WikiError::StatusError(code) => retryable_status(*code),
+            WikiError::RequestError(err) => retryable_error(err),
+            // probably a maintenance page or a cut off response
+            WikiError::Parse(_) => true,
+            WikiError::ApiError { code, .. } => retryable_api_error(code),
+            // asking the same way will get the same warning
+            WikiError::ApiWarning { .. } => false,
+            WikiError::Missing(_) => false,
+        }
+    }
+}
+
+impl Retryable for RedditError
+{
+    fn retryable(&self) -> bool
+    {
+        match self
+        {
+            // the token may have expired early, it will be fetched again
+            RedditError::Unauthorized => true,
+            RedditError::BadCredentials => false,
+            // the maintainer can still run the authorize command
+            RedditError::NoRefreshToken(_) => true,
+            RedditError::OtherStatus(code) => retryable_status(*code),
+            RedditError::OtherError(err) => retryable_error(err),
+            RedditError::RateLimit { .. } => true,
+            // the thread or comment won't come back
+            RedditError::ThreadLocked => false,
+            RedditError::DeletedComment => false,
+            RedditError::TooOld => false,
+            RedditError::ApiError { .. } => false,
+        }
+    }
+}
+
+fn retryable_status(code: StatusCode) -> bool
+{
+    code.is_server_error() || code == StatusCode::TOO_MANY_REQUESTS
+}
+
+fn retryable_error(err: &::reqwest::Error) -> bool
+{
+    match err.status()
+    {
+        Some(code) => retryable_status(code),
+        None => err.is_timeout() || err.is_connect() || err.is_request() || err.is_body(),
+    }
+}
+
+// MediaWiki error codes for problems on the wiki's end
+fn retryable_api_error(code: &str) -> bool
+{
+    code == "maxlag" || code == "readonly" || code == "ratelimited" ||
+        code.starts_with("internal_api_error")
+}
+
+// a random number in 0..max
+fn jitter(max: u64) -> u64
+{
+    let bytes = Uuid::new_v4();
+    let bytes = bytes.as_bytes();
+
+    let mut n = 0u64;
+    for b in bytes[0..8].iter()
+    {
+        n = (n << 8) | *b as u64;
+    }
+
+    if max == 0 { 0 } else { n % max }
+}
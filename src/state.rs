@@ -0,0 +1,64 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+/// Replace a file in the state directory
+///
+/// `write` fills in a temporary file, which is then renamed over `path`, so a
+/// crash can't leave half a file. The directory is created if it is missing.
+pub fn replace<P, F>(path: P, write: F) -> io::Result<()>
+    where P: AsRef<Path>,
+          F: FnOnce(&mut File) -> io::Result<()>
+{
+    let path = path.as_ref();
+
+    if let Some(dir) = path.parent()
+    {
+        fs::create_dir_all(dir)?;
+    }
+
+    let tmp = path.with_extension("tmp");
+    {
+        let mut file = File::create(&tmp)?;
+        write(&mut file)?;
+        file.sync_all()?;
+    }
+
+    fs::rename(&tmp, path)
+}
+
+/// Load a file saved by `save_json`
+pub fn load_json<T: DeserializeOwned, P: AsRef<Path>>(path: P) -> io::Result<T>
+{
+    let file = BufReader::new(File::open(path)?);
+
+    ::serde_json::from_reader(file)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Save a value as JSON, replacing the file with `replace`
+pub fn save_json<T: Serialize, P: AsRef<Path>>(path: P, value: &T) -> io::Result<()>
+{
+    replace(path, |file|
+    {
+        let mut writer = BufWriter::new(file);
+        ::serde_json::to_writer(&mut writer, value)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+        // dropping the writer would hide an error writing the end of the file
+        writer.flush()
+    })
+}
+
+/// The current time, in seconds since the unix epoch
+pub fn now() -> u64
+{
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}